@@ -0,0 +1,358 @@
+use super::error::{CacheError, CacheResult};
+use super::metrics::{COMPRESSION_INPUT_BYTES, COMPRESSION_OUTPUT_BYTES, COMPRESSION_SECONDS};
+use super::pattern::KeyPattern;
+use super::schema::{Cache, EntryInfo, Expiry, KeyPage, Precondition};
+use super::value::CacheValue;
+use async_trait::async_trait;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// zstd's own default, a good trade of ratio against CPU for JSON.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression algorithm of a stored value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+    Lz4,
+}
+
+impl Codec {
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> CacheResult<Vec<u8>> {
+        match self {
+            Codec::Zstd => Ok(zstd::bulk::compress(data, ZSTD_LEVEL)?),
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> CacheResult<Vec<u8>> {
+        match self {
+            Codec::Zstd => zstd::stream::decode_all(data)
+                .map_err(|e| CacheError::Serialization(format!("invalid zstd data: {}", e))),
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| CacheError::Serialization(format!("invalid lz4 data: {}", e))),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            other => Err(format!("unknown compression codec: {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// Codec for new values; `None` stores them uncompressed.
+    pub codec: Option<Codec>,
+    /// Values smaller than this many bytes are stored uncompressed.
+    pub min_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            codec: None,
+            min_bytes: 1024,
+        }
+    }
+}
+
+/// Compresses values on their way into another backend and decompresses
+/// them on the way out.
+///
+/// The codec travels with each value, so entries written with another codec,
+/// or by a pod without compression, keep reading back; this wrapper
+/// decompresses them even when its own `codec` is `None`. A value is only
+/// stored compressed if that makes it smaller. Counters are passed through
+/// untouched: they are far below any sensible threshold.
+pub struct CompressedCache {
+    inner: Arc<dyn Cache<CacheValue>>,
+    config: CompressionConfig,
+}
+
+impl CompressedCache {
+    pub fn new(inner: Arc<dyn Cache<CacheValue>>, config: CompressionConfig) -> Self {
+        Self { inner, config }
+    }
+
+    fn compress(&self, value: CacheValue) -> CacheValue {
+        let Some(codec) = self.config.codec else {
+            return value;
+        };
+        if value.codec.is_some() || value.data.len() < self.config.min_bytes {
+            return value;
+        }
+        let started = Instant::now();
+        let compressed = codec.compress(&value.data);
+        COMPRESSION_SECONDS
+            .with_label_values(&[codec.name(), "compress"])
+            .inc_by(started.elapsed().as_secs_f64());
+        match compressed {
+            Ok(compressed) if compressed.len() < value.data.len() => {
+                COMPRESSION_INPUT_BYTES
+                    .with_label_values(&[codec.name()])
+                    .inc_by(value.data.len() as f64);
+                COMPRESSION_OUTPUT_BYTES
+                    .with_label_values(&[codec.name()])
+                    .inc_by(compressed.len() as f64);
+                CacheValue {
+                    data: compressed.into(),
+                    codec: Some(codec),
+                    ..value
+                }
+            }
+            Ok(_) => value,
+            Err(e) => {
+                warn!("Storing value uncompressed: {}", e);
+                value
+            }
+        }
+    }
+
+    fn decompress(value: CacheValue) -> CacheResult<CacheValue> {
+        let Some(codec) = value.codec else {
+            return Ok(value);
+        };
+        let started = Instant::now();
+        let data = codec.decompress(&value.data);
+        COMPRESSION_SECONDS
+            .with_label_values(&[codec.name(), "decompress"])
+            .inc_by(started.elapsed().as_secs_f64());
+        Ok(CacheValue {
+            data: data?.into(),
+            codec: None,
+            ..value
+        })
+    }
+}
+
+#[async_trait]
+impl Cache<CacheValue> for CompressedCache {
+    async fn insert_item(&self, key: String, value: CacheValue, ttl: u64) -> CacheResult<()> {
+        self.inner.insert_item(key, self.compress(value), ttl).await
+    }
+
+    async fn retrieve_item(&self, key: &str) -> CacheResult<Option<CacheValue>> {
+        self.inner
+            .retrieve_item(key)
+            .await?
+            .map(Self::decompress)
+            .transpose()
+    }
+
+    async fn remove_item(&self, key: &str) -> CacheResult<()> {
+        self.inner.remove_item(key).await
+    }
+
+    async fn write_item(
+        &self,
+        key: String,
+        value: CacheValue,
+        ttl: Option<u64>,
+        condition: Precondition,
+        tags: &[String],
+        sliding: bool,
+    ) -> CacheResult<Option<u64>> {
+        self.inner
+            .write_item(key, self.compress(value), ttl, condition, tags, sliding)
+            .await
+    }
+
+    async fn invalidate_tag(&self, tag: &str) -> CacheResult<u64> {
+        self.inner.invalidate_tag(tag).await
+    }
+
+    async fn increment(&self, key: &str, delta: i64, ttl: Option<u64>) -> CacheResult<i64> {
+        self.inner.increment(key, delta, ttl).await
+    }
+
+    async fn retrieve_ttl(&self, key: &str) -> CacheResult<Option<Option<u64>>> {
+        self.inner.retrieve_ttl(key).await
+    }
+
+    async fn set_expiry(&self, key: &str, expiry: Expiry) -> CacheResult<bool> {
+        self.inner.set_expiry(key, expiry).await
+    }
+
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> CacheResult<KeyPage> {
+        self.inner.scan_keys(prefix, cursor, limit).await
+    }
+
+    async fn remove_matching(&self, pattern: &KeyPattern) -> CacheResult<u64> {
+        self.inner.remove_matching(pattern).await
+    }
+
+    async fn retrieve_versioned(&self, key: &str) -> CacheResult<Option<(CacheValue, u64)>> {
+        match self.inner.retrieve_versioned(key).await? {
+            Some((value, version)) => Ok(Some((Self::decompress(value)?, version))),
+            None => Ok(None),
+        }
+    }
+
+    async fn retrieve_entry(&self, key: &str) -> CacheResult<Option<EntryInfo<CacheValue>>> {
+        match self.inner.retrieve_entry(key).await? {
+            Some(entry) => Ok(Some(EntryInfo {
+                value: Self::decompress(entry.value)?,
+                ..entry
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn retrieve_items(&self, keys: &[String]) -> Vec<CacheResult<Option<CacheValue>>> {
+        self.inner
+            .retrieve_items(keys)
+            .await
+            .into_iter()
+            .map(|result| result?.map(Self::decompress).transpose())
+            .collect()
+    }
+
+    async fn insert_items(&self, items: Vec<(String, CacheValue, u64)>) -> Vec<CacheResult<()>> {
+        let items = items
+            .into_iter()
+            .map(|(key, value, ttl)| (key, self.compress(value), ttl))
+            .collect();
+        self.inner.insert_items(items).await
+    }
+
+    async fn remove_items(&self, keys: &[String]) -> Vec<CacheResult<()>> {
+        self.inner.remove_items(keys).await
+    }
+
+    async fn invalidate_expired(&self) {
+        self.inner.invalidate_expired().await
+    }
+
+    async fn persist(&self) -> CacheResult<()> {
+        self.inner.persist().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::InMemoryCache;
+
+    fn compressed(codec: Option<Codec>) -> (CompressedCache, Arc<InMemoryCache<CacheValue>>) {
+        let inner = Arc::new(InMemoryCache::new());
+        let cache = CompressedCache::new(
+            Arc::clone(&inner) as Arc<dyn Cache<CacheValue>>,
+            CompressionConfig {
+                codec,
+                min_bytes: 64,
+            },
+        );
+        (cache, inner)
+    }
+
+    fn document() -> CacheValue {
+        let json = format!("[{}]", vec![r#"{"id":1,"name":"item"}"#; 100].join(","));
+        CacheValue {
+            written_at: Some(1_700_000_000_000),
+            hash: Some(7),
+            ..CacheValue::new(json, Some("application/json".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_values_round_trip_compressed() {
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            let (cache, inner) = compressed(Some(codec));
+            cache
+                .insert_item("doc".into(), document(), 60)
+                .await
+                .unwrap();
+
+            let stored = inner.retrieve_item("doc").await.unwrap().unwrap();
+            assert_eq!(stored.codec, Some(codec));
+            assert!(stored.data.len() < document().data.len() / 5);
+            assert_eq!(cache.retrieve_item("doc").await.unwrap(), Some(document()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_small_and_incompressible_values_stay_raw() {
+        let (cache, inner) = compressed(Some(Codec::Zstd));
+        let small = CacheValue::from("short".to_string());
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect();
+        cache.insert_item("small".into(), small, 60).await.unwrap();
+        cache
+            .insert_item("noise".into(), CacheValue::new(noise, None), 60)
+            .await
+            .unwrap();
+
+        for key in ["small", "noise"] {
+            let stored = inner.retrieve_item(key).await.unwrap().unwrap();
+            assert_eq!(stored.codec, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_entries_read_back_without_compression() {
+        let (writer, inner) = compressed(Some(Codec::Lz4));
+        writer
+            .insert_item("doc".into(), document(), 60)
+            .await
+            .unwrap();
+        inner
+            .insert_item("plain".into(), CacheValue::from("raw".to_string()), 60)
+            .await
+            .unwrap();
+
+        let reader = CompressedCache::new(inner, CompressionConfig::default());
+        let keys = vec!["doc".to_string(), "plain".to_string()];
+        assert_eq!(
+            reader.retrieve_items(&keys).await,
+            vec![
+                Ok(Some(document())),
+                Ok(Some(CacheValue::from("raw".to_string())))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_data_is_a_serialization_error() {
+        let (cache, inner) = compressed(None);
+        let corrupt = CacheValue {
+            codec: Some(Codec::Zstd),
+            ..CacheValue::from("not zstd".to_string())
+        };
+        inner.insert_item("bad".into(), corrupt, 60).await.unwrap();
+        assert!(matches!(
+            cache.retrieve_versioned("bad").await,
+            Err(CacheError::Serialization(_))
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+use super::now_millis;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// Why `Cache::increment` refused to change a value. It reaches callers as
+/// `CacheError::Counter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CounterError {
+    /// The stored value is not an integer.
+    NotAnInteger,
+    /// The result does not fit the backend's integer range.
+    Overflow,
+}
+
+impl fmt::Display for CounterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CounterError::NotAnInteger => write!(f, "value is not an integer"),
+            CounterError::Overflow => write!(f, "increment would overflow"),
+        }
+    }
+}
+
+impl std::error::Error for CounterError {}
+
+/// Reads a counter from a value that is either a JSON integer or a string of
+/// one, such as the `"42"` stored through the HTTP API, or a `CacheValue`
+/// object whose `text` is one.
+pub fn decode<T: Serialize>(value: &T) -> Option<i64> {
+    match serde_json::to_value(value).ok()? {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.parse().ok(),
+        Value::Object(fields) => fields.get("text")?.as_str()?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Builds the value that stores counter `n`, as a number if `T` accepts one
+/// and as a string otherwise.
+pub fn encode<T: DeserializeOwned>(n: i64) -> Option<T> {
+    serde_json::from_value(Value::from(n))
+        .or_else(|_| serde_json::from_value(Value::String(n.to_string())))
+        .ok()
+}
+
+/// Builds the value that stores counter `n` in place of `previous`. A
+/// `CacheValue` object keeps its other fields, with `text` replaced,
+/// `written_at` renewed and `hash` dropped, as it no longer matches; anything
+/// else is replaced by `encode(n)`.
+pub fn replace<T: Serialize + DeserializeOwned>(previous: &T, n: i64) -> Option<T> {
+    match serde_json::to_value(previous).ok()? {
+        Value::Object(mut fields) if fields.contains_key("text") => {
+            fields.insert("text".to_string(), Value::String(n.to_string()));
+            fields.insert("written_at".to_string(), Value::from(now_millis()));
+            fields.remove("hash");
+            serde_json::from_value(Value::Object(fields)).ok()
+        }
+        _ => encode(n),
+    }
+}
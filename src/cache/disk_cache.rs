@@ -0,0 +1,585 @@
+use super::error::{CacheError, CacheResult};
+use super::schema::{Cache, Precondition, MAX_TTL_SECS};
+use super::{now_millis, sync_parent};
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::Mutex;
+use tokio::task;
+
+/// The log is only compacted once it is at least this large.
+const COMPACTION_MIN_BYTES: u64 = 1 << 20;
+
+/// One line of the append-only log. Deadlines are absolute wall-clock
+/// milliseconds since the Unix epoch, so they stay correct across restarts.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record<T> {
+    Set {
+        key: String,
+        value: T,
+        expires_at: u64,
+    },
+    Remove {
+        key: String,
+    },
+}
+
+struct DiskEntry<T> {
+    value: T,
+    expires_at: u64,
+    record_len: u64,
+}
+
+struct DiskIndex<T> {
+    entries: HashMap<String, DiskEntry<T>>,
+    live_bytes: u64,
+}
+
+struct DiskLog {
+    file: Arc<File>,
+    bytes: u64,
+    /// Set once a failed append could not be cut back off the log; later
+    /// records would land on its torn line, so appends are refused.
+    poisoned: bool,
+}
+
+/// Cache backend that survives restarts without Redis.
+///
+/// Every write is appended to a JSON-lines log and mirrored in an in-memory
+/// index. On open the log is replayed; a torn record at the tail, as left by
+/// a crash in the middle of a write, is truncated away. Once most of the log
+/// is dead records it is rewritten from the index and atomically renamed over
+/// the old one.
+///
+/// File I/O runs on the blocking pool under the log lock, which orders the
+/// records; the index is only locked to update it, so reads never wait for
+/// the disk. Writers take the log lock before the index lock.
+pub struct DiskCache<T> {
+    path: PathBuf,
+    sync_writes: bool,
+    log: Mutex<DiskLog>,
+    index: RwLock<DiskIndex<T>>,
+}
+
+fn encode<T: Serialize>(record: &Record<T>) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(record).map_err(io::Error::from)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+impl<T: Serialize + DeserializeOwned> DiskCache<T> {
+    /// Opens or creates the log at `path`. With `sync_writes` every append is
+    /// fsynced, which also protects against power loss rather than just
+    /// process crashes.
+    pub fn open(path: impl AsRef<Path>, sync_writes: bool) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (entries, log_bytes) = Self::replay(&path)?;
+        let live_bytes = entries.values().map(|entry| entry.record_len).sum();
+        let log = OpenOptions::new().append(true).open(&path)?;
+        info!(
+            "Loaded {} entries from disk cache {}",
+            entries.len(),
+            path.display()
+        );
+        Ok(Self {
+            path,
+            sync_writes,
+            log: Mutex::new(DiskLog {
+                file: Arc::new(log),
+                bytes: log_bytes,
+                poisoned: false,
+            }),
+            index: RwLock::new(DiskIndex {
+                entries,
+                live_bytes,
+            }),
+        })
+    }
+
+    fn index(&self) -> RwLockReadGuard<'_, DiskIndex<T>> {
+        self.index.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn index_mut(&self) -> RwLockWriteGuard<'_, DiskIndex<T>> {
+        self.index.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Rebuilds the index from the log and truncates a torn or corrupt last
+    /// record. A corrupt record with others after it fails the open instead,
+    /// since dropping it would drop them too. Returns the index and the
+    /// length of the valid log.
+    fn replay(path: &Path) -> io::Result<(HashMap<String, DiskEntry<T>>, u64)> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let total = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+        let mut entries = HashMap::new();
+        let now = now_millis();
+        let mut valid = 0;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            match serde_json::from_slice::<Record<T>>(&line) {
+                Ok(Record::Set {
+                    key,
+                    value,
+                    expires_at,
+                }) => {
+                    if expires_at > now {
+                        let record_len = read as u64;
+                        entries.insert(
+                            key,
+                            DiskEntry {
+                                value,
+                                expires_at,
+                                record_len,
+                            },
+                        );
+                    } else {
+                        entries.remove(&key);
+                    }
+                }
+                Ok(Record::Remove { key }) => {
+                    entries.remove(&key);
+                }
+                Err(e) if valid + (read as u64) < total => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "corrupt record at offset {} in {}, followed by more records: {}",
+                            valid,
+                            path.display(),
+                            e
+                        ),
+                    ));
+                }
+                Err(e) => {
+                    warn!(
+                        "Corrupt record at offset {} in {}: {}",
+                        valid,
+                        path.display(),
+                        e
+                    );
+                    break;
+                }
+            }
+            valid += read as u64;
+        }
+        if valid < total {
+            warn!(
+                "Truncating {} trailing bytes of {}",
+                total - valid,
+                path.display()
+            );
+            file.set_len(valid)?;
+            file.sync_all()?;
+        }
+        Ok((entries, valid))
+    }
+
+    /// Appends `line` to the log. A failed append is cut back off the log so
+    /// that the next record starts on a line of its own; if even that fails,
+    /// the log is poisoned.
+    async fn append(&self, log: &mut DiskLog, line: Vec<u8>) -> io::Result<()> {
+        if log.poisoned {
+            return Err(io::Error::other(
+                "disk cache log is unusable after a failed write",
+            ));
+        }
+        let file = Arc::clone(&log.file);
+        let sync_writes = self.sync_writes;
+        let len = line.len() as u64;
+        let (written, intact) = task::spawn_blocking(move || {
+            let end = file.metadata()?.len();
+            let written = (&*file).write_all(&line).and_then(|()| {
+                if sync_writes {
+                    file.sync_data()
+                } else {
+                    Ok(())
+                }
+            });
+            let intact = written.is_ok() || file.set_len(end).is_ok();
+            Ok::<_, io::Error>((written, intact))
+        })
+        .await??;
+        if !intact {
+            log.poisoned = true;
+            warn!(
+                "Disk cache {} is poisoned: a failed write could not be truncated",
+                self.path.display()
+            );
+        }
+        written?;
+        log.bytes += len;
+        Ok(())
+    }
+
+    /// Appends a `Set` record and indexes it, replacing any previous entry.
+    async fn set(&self, key: String, value: T, expires_at: u64) -> io::Result<()> {
+        let line = encode(&Record::Set {
+            key: key.clone(),
+            value: &value,
+            expires_at,
+        })?;
+        let record_len = line.len() as u64;
+        let mut log = self.log.lock().await;
+        self.append(&mut log, line).await?;
+        let mut index = self.index_mut();
+        index.live_bytes += record_len;
+        if let Some(previous) = index.entries.insert(
+            key,
+            DiskEntry {
+                value,
+                expires_at,
+                record_len,
+            },
+        ) {
+            index.live_bytes -= previous.record_len;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the log with only the live entries, then renames it into
+    /// place. The index is only locked while the entries are encoded.
+    async fn compact(&self, log: &mut DiskLog) -> io::Result<()> {
+        let lines = {
+            let mut index = self.index_mut();
+            let now = now_millis();
+            index.entries.retain(|_, entry| entry.expires_at > now);
+            let mut lines = Vec::new();
+            for (key, entry) in index.entries.iter_mut() {
+                let line = encode(&Record::Set {
+                    key: key.clone(),
+                    value: &entry.value,
+                    expires_at: entry.expires_at,
+                })?;
+                entry.record_len = line.len() as u64;
+                lines.extend(line);
+            }
+            index.live_bytes = lines.len() as u64;
+            lines
+        };
+        let live_bytes = lines.len() as u64;
+        let path = self.path.clone();
+        let file = task::spawn_blocking(move || {
+            let tmp_path = path.with_extension("compact");
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&lines)?;
+            tmp.sync_all()?;
+            fs::rename(&tmp_path, &path)?;
+            sync_parent(&path)?;
+            OpenOptions::new().append(true).open(&path)
+        })
+        .await??;
+
+        log.file = Arc::new(file);
+        log.bytes = live_bytes;
+        log.poisoned = false;
+        Ok(())
+    }
+}
+
+/// Deadline in Unix milliseconds of an entry written now with `ttl` seconds,
+/// which is cut to `MAX_TTL_SECS`.
+fn expires_at(ttl: u64) -> u64 {
+    now_millis() + ttl.min(MAX_TTL_SECS) * 1000
+}
+
+#[async_trait]
+impl<T> Cache<T> for DiskCache<T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    async fn insert_item(&self, key: String, value: T, ttl: u64) -> CacheResult<()> {
+        Ok(self.set(key, value, expires_at(ttl)).await?)
+    }
+
+    /// Only unconditional writes. Entries without a TTL get a deadline that
+    /// never comes.
+    async fn write_item(
+        &self,
+        key: String,
+        value: T,
+        ttl: Option<u64>,
+        condition: Precondition,
+        tags: &[String],
+        sliding: bool,
+    ) -> CacheResult<Option<u64>> {
+        if condition != Precondition::Always || !tags.is_empty() || sliding {
+            return Err(CacheError::Unsupported(
+                "conditional writes, tags and sliding expiration are not supported by this backend",
+            ));
+        }
+        self.set(key, value, ttl.map_or(u64::MAX, expires_at))
+            .await?;
+        Ok(Some(0))
+    }
+
+    async fn retrieve_item(&self, key: &str) -> CacheResult<Option<T>> {
+        Ok(self
+            .index()
+            .entries
+            .get(key)
+            .filter(|entry| entry.expires_at > now_millis())
+            .map(|entry| entry.value.clone()))
+    }
+
+    async fn remove_item(&self, key: &str) -> CacheResult<()> {
+        let mut log = self.log.lock().await;
+        if !self.index().entries.contains_key(key) {
+            return Ok(());
+        }
+        let line = encode::<T>(&Record::Remove {
+            key: key.to_string(),
+        })?;
+        self.append(&mut log, line).await?;
+        let mut index = self.index_mut();
+        if let Some(previous) = index.entries.remove(key) {
+            index.live_bytes -= previous.record_len;
+        }
+        Ok(())
+    }
+
+    /// Appends are already in the page cache; this flushes them to the device.
+    async fn persist(&self) -> CacheResult<()> {
+        let file = Arc::clone(&self.log.lock().await.file);
+        task::spawn_blocking(move || file.sync_data())
+            .await
+            .map_err(io::Error::from)??;
+        Ok(())
+    }
+
+    /// Expired entries are filtered on read; their space is reclaimed when
+    /// dead records make up more than half of the log.
+    async fn invalidate_expired(&self) {
+        let mut log = self.log.lock().await;
+        let live_bytes = self.index().live_bytes;
+        if log.bytes < COMPACTION_MIN_BYTES || log.bytes < 2 * live_bytes {
+            return;
+        }
+        if let Err(e) = self.compact(&mut log).await {
+            warn!(
+                "Failed to compact disk cache {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheValue;
+    use std::env;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "cache_service_{}_{}_{}.log",
+            name,
+            std::process::id(),
+            now_millis()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_entries_survive_reopen() {
+        let path = temp_log("reopen");
+        {
+            let cache = DiskCache::<String>::open(&path, false).unwrap();
+            cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+            cache.insert_item("b".into(), "2".into(), 60).await.unwrap();
+            cache.insert_item("a".into(), "3".into(), 60).await.unwrap();
+            cache.remove_item("b").await.unwrap();
+        }
+
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("3".to_string())
+        );
+        assert_eq!(cache.retrieve_item("b").await.unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_binary_values_survive_reopen() {
+        let path = temp_log("binary");
+        let blob = CacheValue::new(vec![0u8, 255, 10, 13], Some("image/png".to_string()));
+        {
+            let cache = DiskCache::<CacheValue>::open(&path, false).unwrap();
+            cache
+                .insert_item("blob".into(), blob.clone(), 60)
+                .await
+                .unwrap();
+        }
+
+        let cache = DiskCache::<CacheValue>::open(&path, false).unwrap();
+        assert_eq!(cache.retrieve_item("blob").await.unwrap(), Some(blob));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deadlines_are_wall_clock() {
+        let path = temp_log("deadline");
+        let past = now_millis() - 1;
+        let future = now_millis() + 60_000;
+        let mut log = encode(&Record::Set {
+            key: "expired".to_string(),
+            value: "x".to_string(),
+            expires_at: past,
+        })
+        .unwrap();
+        log.extend(
+            encode(&Record::Set {
+                key: "live".to_string(),
+                value: "y".to_string(),
+                expires_at: future,
+            })
+            .unwrap(),
+        );
+        fs::write(&path, log).unwrap();
+
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        assert_eq!(cache.retrieve_item("expired").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("live").await.unwrap(),
+            Some("y".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_torn_tail_is_truncated() {
+        let path = temp_log("torn");
+        {
+            let cache = DiskCache::<String>::open(&path, false).unwrap();
+            cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+        }
+        let intact = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"op":"set","key":"b","val"#).unwrap();
+        drop(file);
+
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        assert_eq!(cache.retrieve_item("b").await.unwrap(), None);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+
+        cache.insert_item("c".into(), "2".into(), 60).await.unwrap();
+        drop(cache);
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        assert_eq!(
+            cache.retrieve_item("c").await.unwrap(),
+            Some("2".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_record_before_others_fails_open() {
+        let path = temp_log("corrupt");
+        {
+            let cache = DiskCache::<String>::open(&path, false).unwrap();
+            cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"op\":\"set\",\"key\n").unwrap();
+        file.write_all(
+            &encode(&Record::Set {
+                key: "b".to_string(),
+                value: "2".to_string(),
+                expires_at: now_millis() + 60_000,
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        drop(file);
+        let before = fs::read(&path).unwrap();
+
+        let opened = DiskCache::<String>::open(&path, false);
+        assert_eq!(
+            opened.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+        assert_eq!(fs::read(&path).unwrap(), before);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_append_that_cannot_be_undone_poisons_the_log() {
+        let path = temp_log("poisoned");
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+        // Neither writable nor truncatable, so the append cannot be undone.
+        cache.log.lock().await.file = Arc::new(File::open(&path).unwrap());
+
+        assert!(cache.insert_item("b".into(), "2".into(), 60).await.is_err());
+        assert_eq!(cache.retrieve_item("b").await.unwrap(), None);
+        assert!(cache.log.lock().await.poisoned);
+        assert!(cache.remove_item("a").await.is_err());
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        drop(cache);
+
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compaction_drops_dead_records() {
+        let path = temp_log("compact");
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        let value = "v".repeat(1024);
+        for _ in 0..2048 {
+            cache
+                .insert_item("hot".into(), value.clone(), 60)
+                .await
+                .unwrap();
+        }
+        cache
+            .insert_item("other".into(), "x".into(), 60)
+            .await
+            .unwrap();
+        let before = fs::metadata(&path).unwrap().len();
+
+        cache.invalidate_expired().await;
+
+        let after = fs::metadata(&path).unwrap().len();
+        assert!(after < before / 100, "{} -> {}", before, after);
+        drop(cache);
+        let cache = DiskCache::<String>::open(&path, false).unwrap();
+        assert_eq!(cache.retrieve_item("hot").await.unwrap(), Some(value));
+        assert_eq!(
+            cache.retrieve_item("other").await.unwrap(),
+            Some("x".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use super::counter::CounterError;
+use std::fmt;
+use std::io;
+
+/// Why a cache operation failed. A miss is not an error: reads report it as
+/// `Ok(None)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheError {
+    /// The backend could not be reached: a pool timeout, a refused or dropped
+    /// connection, or a cluster or failover that is not ready. Retrying later
+    /// may succeed.
+    Unavailable(String),
+    /// A value could not be encoded, or a stored one could not be decoded.
+    Serialization(String),
+    /// The entry alone exceeds the backend's memory budget.
+    TooLarge(String),
+    /// The entry was written but evicted at once to keep the cache within
+    /// its limits, as an admission filter does with entries it rates below
+    /// the ones they would displace.
+    NotAdmitted(String),
+    /// An argument the backend cannot use, such as a malformed scan cursor.
+    InvalidInput(String),
+    /// The backend does not implement the operation.
+    Unsupported(&'static str),
+    /// `Cache::increment` refused to change the value.
+    Counter(CounterError),
+    /// Any other failure of the backend, such as a disk write or a Redis
+    /// script error.
+    Backend(String),
+}
+
+pub type CacheResult<T> = Result<T, CacheError>;
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Unavailable(e) => write!(f, "backend unavailable: {}", e),
+            CacheError::Serialization(e) => write!(f, "serialization failed: {}", e),
+            CacheError::TooLarge(e)
+            | CacheError::NotAdmitted(e)
+            | CacheError::InvalidInput(e)
+            | CacheError::Backend(e) => {
+                write!(f, "{}", e)
+            }
+            CacheError::Unsupported(e) => write!(f, "{}", e),
+            CacheError::Counter(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<CounterError> for CacheError {
+    fn from(e: CounterError) -> Self {
+        CacheError::Counter(e)
+    }
+}
+
+impl From<serde_json::Error> for CacheError {
+    fn from(e: serde_json::Error) -> Self {
+        CacheError::Serialization(e.to_string())
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError::Backend(e.to_string())
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// Decides which entry an `InMemoryCache` gives up when it runs out of room.
+///
+/// The store notifies the policy about every insert, hit and removal of a key;
+/// the policy only tracks keys, never values.
+pub trait EvictionPolicy: Send + Sync {
+    fn on_insert(&mut self, key: &str);
+    fn on_access(&mut self, key: &str);
+    fn on_remove(&mut self, key: &str);
+    /// The key that should be evicted next, if any.
+    fn victim(&self) -> Option<String>;
+    /// Whether a newly written `candidate` is worth keeping at the cost of
+    /// evicting `victim`. Policies without an admission filter accept everything.
+    fn admit(&mut self, _candidate: &str, _victim: &str) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicyKind {
+    #[default]
+    Lru,
+    Lfu,
+    Fifo,
+    TinyLfu,
+}
+
+impl EvictionPolicyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionPolicyKind::Lru => "lru",
+            EvictionPolicyKind::Lfu => "lfu",
+            EvictionPolicyKind::Fifo => "fifo",
+            EvictionPolicyKind::TinyLfu => "tinylfu",
+        }
+    }
+
+    /// Builds the policy. `capacity_hint` sizes the frequency sketch of TinyLFU.
+    pub fn build(&self, capacity_hint: usize) -> Box<dyn EvictionPolicy> {
+        match self {
+            EvictionPolicyKind::Lru => Box::new(Lru::default()),
+            EvictionPolicyKind::Lfu => Box::new(Lfu::default()),
+            EvictionPolicyKind::Fifo => Box::new(Fifo::default()),
+            EvictionPolicyKind::TinyLfu => Box::new(TinyLfu::new(capacity_hint)),
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EvictionPolicyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lru" => Ok(EvictionPolicyKind::Lru),
+            "lfu" => Ok(EvictionPolicyKind::Lfu),
+            "fifo" => Ok(EvictionPolicyKind::Fifo),
+            "tinylfu" | "tiny_lfu" => Ok(EvictionPolicyKind::TinyLfu),
+            other => Err(format!("unknown eviction policy: {}", other)),
+        }
+    }
+}
+
+/// Keys ordered by a monotonically increasing tick; the front is the oldest.
+#[derive(Default)]
+struct Queue {
+    tick: u64,
+    ticks: HashMap<String, u64>,
+    order: BTreeMap<u64, String>,
+}
+
+impl Queue {
+    fn push_back(&mut self, key: &str) {
+        self.tick += 1;
+        if let Some(old) = self.ticks.insert(key.to_string(), self.tick) {
+            self.order.remove(&old);
+        }
+        self.order.insert(self.tick, key.to_string());
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn front(&self) -> Option<String> {
+        self.order.values().next().cloned()
+    }
+}
+
+/// Evicts the least recently used key.
+#[derive(Default)]
+pub struct Lru {
+    queue: Queue,
+}
+
+impl EvictionPolicy for Lru {
+    fn on_insert(&mut self, key: &str) {
+        self.queue.push_back(key);
+    }
+
+    fn on_access(&mut self, key: &str) {
+        self.queue.push_back(key);
+    }
+
+    fn on_remove(&mut self, key: &str) {
+        self.queue.remove(key);
+    }
+
+    fn victim(&self) -> Option<String> {
+        self.queue.front()
+    }
+}
+
+/// Evicts the oldest key regardless of how often it is read.
+#[derive(Default)]
+pub struct Fifo {
+    queue: Queue,
+}
+
+impl EvictionPolicy for Fifo {
+    fn on_insert(&mut self, key: &str) {
+        self.queue.push_back(key);
+    }
+
+    fn on_access(&mut self, _key: &str) {}
+
+    fn on_remove(&mut self, key: &str) {
+        self.queue.remove(key);
+    }
+
+    fn victim(&self) -> Option<String> {
+        self.queue.front()
+    }
+}
+
+/// Evicts the least frequently used key, breaking ties by age.
+#[derive(Default)]
+pub struct Lfu {
+    tick: u64,
+    counts: HashMap<String, (u64, u64)>,
+    order: BTreeSet<(u64, u64, String)>,
+}
+
+impl Lfu {
+    fn bump(&mut self, key: &str) {
+        self.tick += 1;
+        let (count, _) = match self.counts.get(key) {
+            Some(&(count, tick)) => {
+                self.order.remove(&(count, tick, key.to_string()));
+                (count, tick)
+            }
+            None => (0, 0),
+        };
+        self.counts.insert(key.to_string(), (count + 1, self.tick));
+        self.order.insert((count + 1, self.tick, key.to_string()));
+    }
+}
+
+impl EvictionPolicy for Lfu {
+    fn on_insert(&mut self, key: &str) {
+        self.bump(key);
+    }
+
+    fn on_access(&mut self, key: &str) {
+        self.bump(key);
+    }
+
+    fn on_remove(&mut self, key: &str) {
+        if let Some((count, tick)) = self.counts.remove(key) {
+            self.order.remove(&(count, tick, key.to_string()));
+        }
+    }
+
+    fn victim(&self) -> Option<String> {
+        self.order.iter().next().map(|(_, _, key)| key.clone())
+    }
+}
+
+const SKETCH_DEPTH: usize = 4;
+
+/// Count-min sketch of 4-bit-saturating counters that halves itself after a
+/// fixed number of increments, so frequencies reflect recent history.
+struct FrequencySketch {
+    table: Vec<[u8; SKETCH_DEPTH]>,
+    mask: u64,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(capacity: usize) -> Self {
+        let width = capacity.max(16).next_power_of_two();
+        Self {
+            table: vec![[0; SKETCH_DEPTH]; width],
+            mask: width as u64 - 1,
+            additions: 0,
+            sample_size: width * 10,
+        }
+    }
+
+    fn slots(&self, key: &str) -> [usize; SKETCH_DEPTH] {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash, hash.rotate_left(32) | 1);
+        let mut slots = [0; SKETCH_DEPTH];
+        for (row, slot) in slots.iter_mut().enumerate() {
+            *slot = (h1.wrapping_add((row as u64).wrapping_mul(h2)) & self.mask) as usize;
+        }
+        slots
+    }
+
+    fn increment(&mut self, key: &str) {
+        for (row, slot) in self.slots(key).into_iter().enumerate() {
+            let counter = &mut self.table[slot][row];
+            *counter = (*counter + 1).min(15);
+        }
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.table
+                .iter_mut()
+                .flat_map(|counters| counters.iter_mut())
+                .for_each(|counter| *counter /= 2);
+            self.additions /= 2;
+        }
+    }
+
+    fn estimate(&self, key: &str) -> u8 {
+        self.slots(key)
+            .into_iter()
+            .enumerate()
+            .map(|(row, slot)| self.table[slot][row])
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+/// LRU ordering behind a TinyLFU admission filter: a new key only displaces
+/// the LRU victim if it has been seen at least as often recently, which keeps
+/// one-off scans from flushing the hot set.
+pub struct TinyLfu {
+    lru: Lru,
+    sketch: FrequencySketch,
+}
+
+impl TinyLfu {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lru: Lru::default(),
+            sketch: FrequencySketch::new(capacity),
+        }
+    }
+}
+
+impl EvictionPolicy for TinyLfu {
+    fn on_insert(&mut self, key: &str) {
+        self.sketch.increment(key);
+        self.lru.on_insert(key);
+    }
+
+    fn on_access(&mut self, key: &str) {
+        self.sketch.increment(key);
+        self.lru.on_access(key);
+    }
+
+    fn on_remove(&mut self, key: &str) {
+        self.lru.on_remove(key);
+    }
+
+    fn victim(&self) -> Option<String> {
+        self.lru.victim()
+    }
+
+    fn admit(&mut self, candidate: &str, victim: &str) -> bool {
+        self.sketch.estimate(candidate) >= self.sketch.estimate(victim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_victim_is_least_recently_accessed() {
+        let mut policy = Lru::default();
+        policy.on_insert("a");
+        policy.on_insert("b");
+        policy.on_access("a");
+
+        assert_eq!(policy.victim().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_fifo_ignores_accesses() {
+        let mut policy = Fifo::default();
+        policy.on_insert("a");
+        policy.on_insert("b");
+        policy.on_access("a");
+
+        assert_eq!(policy.victim().as_deref(), Some("a"));
+        policy.on_remove("a");
+        assert_eq!(policy.victim().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_lfu_victim_is_least_frequently_accessed() {
+        let mut policy = Lfu::default();
+        policy.on_insert("a");
+        policy.on_insert("b");
+        policy.on_insert("c");
+        policy.on_access("a");
+        policy.on_access("a");
+        policy.on_access("c");
+
+        assert_eq!(policy.victim().as_deref(), Some("b"));
+        policy.on_remove("b");
+        assert_eq!(policy.victim().as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn test_tinylfu_rejects_cold_candidate_over_hot_victim() {
+        let mut policy = TinyLfu::new(16);
+        policy.on_insert("hot");
+        for _ in 0..5 {
+            policy.on_access("hot");
+        }
+        policy.on_insert("cold");
+
+        assert_eq!(policy.victim().as_deref(), Some("hot"));
+        assert!(!policy.admit("cold", "hot"));
+        assert!(policy.admit("hot", "cold"));
+    }
+
+    #[test]
+    fn test_parse_policy_kind() {
+        assert_eq!("LFU".parse(), Ok(EvictionPolicyKind::Lfu));
+        assert_eq!("tinylfu".parse(), Ok(EvictionPolicyKind::TinyLfu));
+        assert!("random".parse::<EvictionPolicyKind>().is_err());
+    }
+}
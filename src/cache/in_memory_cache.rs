@@ -1,12 +1,415 @@
-use super::schema::Cache;
+use super::counter::{self, CounterError};
+use super::error::{CacheError, CacheResult};
+use super::eviction::{EvictionPolicy, EvictionPolicyKind};
+use super::metrics::{EVICTION_COUNTER, HIT_COUNTER, MISS_COUNTER};
+use super::pattern::KeyPattern;
+use super::schema::{Cache, EntryInfo, Expiry, KeyInfo, KeyPage, Precondition, MAX_TTL_SECS};
+use super::{now_millis, sync_parent};
 use async_trait::async_trait;
-use std::collections::HashMap;
-use std::io;
+use log::info;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
 use tokio::sync::RwLock;
-use tokio::time::{self, Duration, Instant};
+use tokio::task;
+use tokio::time::{Duration, Instant};
 
+/// Approximate number of bytes a value occupies, used to enforce the memory budget.
+pub trait Weigh {
+    fn weigh(&self) -> usize;
+}
+
+impl Weigh for String {
+    fn weigh(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Capacity limits, eviction policy and shard count for an `InMemoryCache`.
+/// A `None` limit leaves that dimension unbounded. Limits apply to the cache
+/// as a whole and are split evenly across shards.
+#[derive(Clone, Debug)]
+pub struct InMemoryConfig {
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub policy: EvictionPolicyKind,
+    pub shards: usize,
+    /// Where `Cache::persist` writes snapshots; `None` disables them.
+    pub snapshot_path: Option<PathBuf>,
+}
+
+impl Default for InMemoryConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: None,
+            max_bytes: None,
+            policy: EvictionPolicyKind::default(),
+            shards: 1,
+            snapshot_path: None,
+        }
+    }
+}
+
+impl InMemoryConfig {
+    /// The limits each of `shards` independently locked stores enforces.
+    fn per_shard(&self) -> Self {
+        let shards = self.shards.max(1);
+        Self {
+            max_entries: self.max_entries.map(|max| max.div_ceil(shards)),
+            max_bytes: self.max_bytes.map(|max| max.div_ceil(shards)),
+            shards: 1,
+            ..self.clone()
+        }
+    }
+}
+
+/// Sizes policy bookkeeping when only a byte budget is configured.
+const DEFAULT_CAPACITY_HINT: usize = 10_000;
+/// Bumped whenever the snapshot layout changes incompatibly.
+const SNAPSHOT_VERSION: u32 = 1;
+/// Most expired keys removed per shard lock acquisition.
+const SWEEP_BATCH: usize = 256;
+/// Most expired keys `scan_keys` steps over per shard in one call.
+const SCAN_SKIP_BUDGET: usize = 256;
+/// Upper bound on the time one expiry pass spends removing keys.
+const SWEEP_BUDGET: Duration = Duration::from_millis(10);
+/// Most a read lets a sliding deadline lag behind before moving it, which
+/// takes the write lock; a quarter of the TTL for TTLs under 4 seconds.
+const SLIDE_STEP: Duration = Duration::from_secs(1);
+/// Lifetime of entries written without a TTL; `Instant` has no "never".
+const NO_EXPIRY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Remaining TTL in seconds, rounded up; `None` for entries that never
+/// expire, which are at least half of `NO_EXPIRY` away from their deadline.
+fn remaining_ttl(expiry: Instant, now: Instant) -> Option<u64> {
+    let left = expiry - now;
+    (left < NO_EXPIRY / 2).then(|| left.as_millis().div_ceil(1000) as u64)
+}
+
+/// `ttl` seconds as a `Duration`, cut to `MAX_TTL_SECS` so that adding it to
+/// an `Instant` cannot overflow.
+fn ttl_duration(ttl: u64) -> Duration {
+    Duration::from_secs(ttl.min(MAX_TTL_SECS))
+}
+
+/// The error for a write whose entry was evicted as soon as it was made,
+/// turned away by the admission filter or picked as the victim itself.
+fn not_kept(key: &str) -> CacheError {
+    CacheError::NotAdmitted(format!(
+        "{} was evicted to keep the cache within its limits",
+        key
+    ))
+}
+
+struct Entry<T> {
+    value: T,
+    expiry: Instant,
+    size: usize,
+    version: u64,
+    tags: Vec<String>,
+    /// For sliding entries, the TTL each read restarts.
+    sliding: Option<Duration>,
+}
+
+/// On-disk snapshot. TTLs are stored as the milliseconds left at `saved_at`
+/// (wall-clock), so time spent while the process was down is subtracted on load.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<T> {
+    version: u32,
+    saved_at: u64,
+    entries: Vec<SnapshotEntry<T>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry<T> {
+    key: String,
+    value: T,
+    ttl_ms: u64,
+    /// Missing from snapshots taken before entries were versioned.
+    #[serde(default)]
+    version: u64,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    sliding_ms: Option<u64>,
+}
+
+/// The map plus the eviction policy tracking its keys, an ordered index of
+/// the keys for prefix scans, the keys carrying each tag, and a min-heap of
+/// expiry deadlines. Heap items are not removed when a key is overwritten or
+/// deleted; stale ones are recognised and skipped when they are popped.
+struct Store<T> {
+    entries: HashMap<String, Entry<T>>,
+    keys: BTreeSet<String>,
+    tagged: HashMap<String, HashSet<String>>,
+    /// Locked on its own, so that hits can record their access under the
+    /// shard's read lock.
+    policy: Mutex<Box<dyn EvictionPolicy>>,
+    deadlines: BinaryHeap<Reverse<(Instant, String)>>,
+    bytes: usize,
+}
+
+impl<T> Store<T> {
+    fn new(config: &InMemoryConfig) -> Self {
+        let capacity_hint = config.max_entries.unwrap_or(DEFAULT_CAPACITY_HINT);
+        Self {
+            entries: HashMap::new(),
+            keys: BTreeSet::new(),
+            tagged: HashMap::new(),
+            policy: Mutex::new(config.policy.build(capacity_hint)),
+            deadlines: BinaryHeap::new(),
+            bytes: 0,
+        }
+    }
+
+    fn policy(&mut self) -> &mut dyn EvictionPolicy {
+        self.policy
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+    }
+
+    fn insert(&mut self, key: String, entry: Entry<T>) {
+        self.bytes += entry.size;
+        self.deadlines.push(Reverse((entry.expiry, key.clone())));
+        for tag in &entry.tags {
+            self.tagged
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        match self.entries.insert(key.clone(), entry) {
+            Some(previous) => {
+                self.bytes -= previous.size;
+                self.untag(&key, &previous.tags);
+                self.policy().on_access(&key);
+            }
+            None => {
+                self.policy().on_insert(&key);
+                self.keys.insert(key);
+            }
+        }
+        self.prune_deadlines();
+    }
+
+    /// Moves the deadline of the live entry at `key`, removing it if the new
+    /// deadline is not after `now`. Returns whether there was such an entry.
+    fn set_expiry(&mut self, key: &str, expiry: Instant, now: Instant) -> bool {
+        match self.entries.get_mut(key) {
+            Some(entry) if now < entry.expiry => {
+                entry.expiry = expiry;
+                entry.sliding = None;
+            }
+            _ => return false,
+        }
+        if expiry <= now {
+            self.remove(key);
+        } else {
+            self.deadlines.push(Reverse((expiry, key.to_string())));
+            self.prune_deadlines();
+        }
+        true
+    }
+
+    fn prune_deadlines(&mut self) {
+        if self.deadlines.len() > 2 * self.entries.len() + SWEEP_BATCH {
+            self.rebuild_deadlines();
+        }
+    }
+
+    /// Drops stale heap items so overwrites cannot grow the heap without bound.
+    fn rebuild_deadlines(&mut self) {
+        self.deadlines = self
+            .entries
+            .iter()
+            .map(|(key, entry)| Reverse((entry.expiry, key.clone())))
+            .collect();
+    }
+
+    /// Drops `key` from the index of each of `tags` it no longer carries.
+    fn untag(&mut self, key: &str, tags: &[String]) {
+        let current = self.entries.get(key).map_or(&[][..], |entry| &entry.tags);
+        let stale: Vec<&String> = tags.iter().filter(|tag| !current.contains(tag)).collect();
+        for tag in stale {
+            if let Some(keys) = self.tagged.get_mut(tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tagged.remove(tag);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry<T>> {
+        let entry = self.entries.remove(key)?;
+        self.untag(key, &entry.tags);
+        self.keys.remove(key);
+        self.policy().on_remove(key);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn over_limits(&self, config: &InMemoryConfig) -> bool {
+        config
+            .max_entries
+            .is_some_and(|max| self.entries.len() > max)
+            || config.max_bytes.is_some_and(|max| self.bytes > max)
+    }
+
+    /// Inserts `entry` and evicts down to the limits. Returns how many
+    /// entries were evicted and whether `key` is among the survivors. Only a
+    /// new key goes through admission; an overwrite already earned its place.
+    fn insert_within_limits(
+        &mut self,
+        config: &InMemoryConfig,
+        key: String,
+        entry: Entry<T>,
+    ) -> (usize, bool) {
+        let overwrite = self.entries.contains_key(&key);
+        self.insert(key.clone(), entry);
+        let evicted = self.evict(config, &key, !overwrite);
+        (evicted, self.entries.contains_key(&key))
+    }
+
+    /// Evicts entries chosen by the policy until the store fits its limits and
+    /// returns how many were dropped. If `admitting` and the admission filter
+    /// prefers the victim, the freshly written `candidate` is dropped instead.
+    fn evict(&mut self, config: &InMemoryConfig, candidate: &str, admitting: bool) -> usize {
+        let mut evicted = 0;
+        while self.over_limits(config) {
+            let Some(mut victim) = self.policy().victim() else {
+                break;
+            };
+            if admitting
+                && victim != candidate
+                && self.entries.contains_key(candidate)
+                && !self.policy().admit(candidate, &victim)
+            {
+                victim = candidate.to_string();
+            }
+            match self.remove(&victim) {
+                Some(_) => evicted += 1,
+                None => self.policy().on_remove(&victim),
+            }
+        }
+        evicted
+    }
+
+    /// `lookup` under the read lock. Hits and plain misses are answered
+    /// here; `None` if the key needs `lookup` under the write lock, because
+    /// its entry expired or is sliding and due to move its deadline.
+    fn peek(&self, key: &str, policy: &str) -> Option<Option<EntryInfo<T>>>
+    where
+        T: Clone,
+    {
+        let now = Instant::now();
+        let Some(entry) = self.entries.get(key) else {
+            MISS_COUNTER.with_label_values(&[policy]).inc();
+            return Some(None);
+        };
+        let lagging = entry
+            .sliding
+            .is_some_and(|window| entry.expiry + (window / 4).min(SLIDE_STEP) < now + window);
+        if now >= entry.expiry || lagging {
+            return None;
+        }
+        self.policy
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .on_access(key);
+        HIT_COUNTER.with_label_values(&[policy]).inc();
+        Some(Some(EntryInfo {
+            value: entry.value.clone(),
+            version: entry.version,
+            ttl: remaining_ttl(entry.expiry, now),
+        }))
+    }
+
+    /// Returns a live value with its version and records the access, counting
+    /// the hit or miss under `policy`. A sliding entry gets a later deadline
+    /// but no new heap item; `remove_expired` requeues it when the old one
+    /// comes due, so reads never touch the heap.
+    fn lookup(&mut self, key: &str, policy: &str) -> Option<EntryInfo<T>>
+    where
+        T: Clone,
+    {
+        let now = Instant::now();
+        let value = match self.entries.get_mut(key) {
+            Some(entry) if now < entry.expiry => {
+                if let Some(window) = entry.sliding {
+                    entry.expiry = now + window;
+                }
+                EntryInfo {
+                    value: entry.value.clone(),
+                    version: entry.version,
+                    ttl: remaining_ttl(entry.expiry, now),
+                }
+            }
+            Some(_) => {
+                self.remove(key);
+                MISS_COUNTER.with_label_values(&[policy]).inc();
+                return None;
+            }
+            None => {
+                MISS_COUNTER.with_label_values(&[policy]).inc();
+                return None;
+            }
+        };
+        self.policy().on_access(key);
+        HIT_COUNTER.with_label_values(&[policy]).inc();
+        Some(value)
+    }
+
+    /// Removes at most `limit` keys whose deadline has passed, touching only
+    /// due heap items, and requeues sliding entries read since their item was
+    /// queued. Returns whether more due items are left.
+    fn remove_expired(&mut self, now: Instant, limit: usize) -> bool {
+        let mut removed = 0;
+        while removed < limit {
+            match self.deadlines.peek() {
+                Some(Reverse((deadline, _))) if *deadline <= now => {}
+                _ => return false,
+            }
+            let Some(Reverse((deadline, key))) = self.deadlines.pop() else {
+                return false;
+            };
+            match self.entries.get(&key) {
+                Some(entry) if entry.expiry == deadline => {
+                    self.remove(&key);
+                    removed += 1;
+                }
+                Some(entry) if entry.sliding.is_some() && entry.expiry > deadline => {
+                    let expiry = entry.expiry;
+                    self.deadlines.push(Reverse((expiry, key)));
+                }
+                _ => {}
+            }
+        }
+        self.deadlines
+            .peek()
+            .is_some_and(|Reverse((deadline, _))| *deadline <= now)
+    }
+}
+
+/// Keys are hashed onto independently locked shards, so writers to different
+/// shards never wait on each other.
 pub struct InMemoryCache<T> {
-    store: RwLock<HashMap<String, (T, Instant)>>,
+    shards: Box<[RwLock<Store<T>>]>,
+    shard_config: InMemoryConfig,
+    hasher: RandomState,
+    sweep_cursor: AtomicUsize,
+    snapshot_path: Option<PathBuf>,
+    /// Last version handed out; shared by all shards so a key that is
+    /// deleted and written again never gets a version it had before.
+    last_version: AtomicU64,
 }
 
 impl<T> Default for InMemoryCache<T> {
@@ -17,42 +420,1581 @@ impl<T> Default for InMemoryCache<T> {
 
 impl<T> InMemoryCache<T> {
     pub fn new() -> Self {
+        Self::with_config(InMemoryConfig::default())
+    }
+
+    pub fn with_config(config: InMemoryConfig) -> Self {
+        let shard_config = config.per_shard();
+        let shards = (0..config.shards.max(1))
+            .map(|_| RwLock::new(Store::new(&shard_config)))
+            .collect();
         Self {
-            store: RwLock::new(HashMap::new()),
+            shards,
+            shard_config,
+            hasher: RandomState::new(),
+            sweep_cursor: AtomicUsize::new(0),
+            snapshot_path: config.snapshot_path,
+            last_version: AtomicU64::new(0),
+        }
+    }
+
+    fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        self.hasher.hash_one(key) as usize % self.shards.len()
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<Store<T>> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Positions in `keys` grouped by the shard each key lives on, so a batch
+    /// takes every shard lock once.
+    fn group_by_shard<'k>(&self, keys: impl Iterator<Item = &'k str>) -> Vec<(usize, Vec<usize>)> {
+        let mut groups = vec![Vec::new(); self.shards.len()];
+        for (position, key) in keys.enumerate() {
+            groups[self.shard_index(key)].push(position);
+        }
+        groups
+            .into_iter()
+            .enumerate()
+            .filter(|(_, positions)| !positions.is_empty())
+            .collect()
+    }
+
+    /// Drops every entry, one shard at a time.
+    pub async fn clear(&self) {
+        for shard in self.shards.iter() {
+            *shard.write().await = Store::new(&self.shard_config);
         }
     }
 }
 
-#[async_trait]
-impl<T: Clone + Send + Sync + 'static> Cache<T> for InMemoryCache<T> {
-    async fn insert_item(&self, key: String, value: T, ttl: u64) -> io::Result<()> {
-        let mut store = self.store.write().await;
-        store.insert(key, (value, Instant::now() + Duration::from_secs(ttl)));
+impl<T: Weigh> InMemoryCache<T> {
+    /// A new entry with the next version. Fails if the key, value and tags
+    /// could never fit in a shard's memory budget.
+    fn new_entry(
+        &self,
+        key: &str,
+        value: T,
+        expiry: Instant,
+        tags: Vec<String>,
+    ) -> CacheResult<Entry<T>> {
+        let size = key.len() + value.weigh() + tags.iter().map(String::len).sum::<usize>();
+        if self.shard_config.max_bytes.is_some_and(|max| size > max) {
+            return Err(CacheError::TooLarge(format!(
+                "item of {} bytes exceeds the cache memory budget",
+                size
+            )));
+        }
+        Ok(Entry {
+            value,
+            expiry,
+            size,
+            version: self.next_version(),
+            tags,
+            sliding: None,
+        })
+    }
+
+    async fn insert_entry(&self, key: String, entry: Entry<T>) -> CacheResult<()> {
+        let mut store = self.shard(&key).write().await;
+        let (evicted, kept) = store.insert_within_limits(&self.shard_config, key.clone(), entry);
+        EVICTION_COUNTER.inc_by(evicted as f64);
+        if !kept {
+            return Err(not_kept(&key));
+        }
         Ok(())
     }
+}
+
+impl<T> InMemoryCache<T>
+where
+    T: Weigh + Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    /// Writes every live entry with its remaining TTL to `path`, through a
+    /// temporary file that is renamed into place. Returns the entry count.
+    pub async fn save_snapshot(&self, path: &Path) -> io::Result<usize> {
+        let now = Instant::now();
+        let mut entries = Vec::new();
+        for shard in self.shards.iter() {
+            let store = shard.read().await;
+            entries.extend(
+                store
+                    .entries
+                    .iter()
+                    .filter(|(_, entry)| entry.expiry > now)
+                    .map(|(key, entry)| SnapshotEntry {
+                        key: key.clone(),
+                        value: entry.value.clone(),
+                        ttl_ms: (entry.expiry - now).as_millis() as u64,
+                        version: entry.version,
+                        tags: entry.tags.clone(),
+                        sliding_ms: entry.sliding.map(|window| window.as_millis() as u64),
+                    }),
+            );
+        }
+        let count = entries.len();
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            saved_at: now_millis(),
+            entries,
+        };
+        let path = path.to_path_buf();
+        task::spawn_blocking(move || {
+            let tmp_path = path.with_extension("tmp");
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, &snapshot)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            fs::rename(&tmp_path, &path)?;
+            sync_parent(&path)
+        })
+        .await??;
+        Ok(count)
+    }
+
+    /// Loads a snapshot written by `save_snapshot`, skipping entries that
+    /// expired in the meantime. Returns the number of entries restored.
+    pub async fn load_snapshot(&self, path: &Path) -> io::Result<usize> {
+        let bytes = tokio::fs::read(path).await?;
+        let snapshot: Snapshot<T> = serde_json::from_slice(&bytes)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported snapshot version {} (expected {})",
+                    snapshot.version, SNAPSHOT_VERSION
+                ),
+            ));
+        }
+        let elapsed = now_millis().saturating_sub(snapshot.saved_at);
+        let now = Instant::now();
+        let mut restored = 0;
+        for entry in snapshot.entries {
+            if entry.ttl_ms <= elapsed {
+                continue;
+            }
+            let expiry = now + Duration::from_millis(entry.ttl_ms - elapsed);
+            let mut restored_entry = self
+                .new_entry(&entry.key, entry.value, expiry, entry.tags)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            restored_entry.sliding = entry.sliding_ms.map(Duration::from_millis);
+            if entry.version > 0 {
+                restored_entry.version = entry.version;
+                self.last_version
+                    .fetch_max(entry.version, Ordering::Relaxed);
+            }
+            if self.insert_entry(entry.key, restored_entry).await.is_ok() {
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+}
+
+#[async_trait]
+impl<T> Cache<T> for InMemoryCache<T>
+where
+    T: Weigh + Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    async fn insert_item(&self, key: String, value: T, ttl: u64) -> CacheResult<()> {
+        let expiry = Instant::now() + ttl_duration(ttl);
+        let entry = self.new_entry(&key, value, expiry, Vec::new())?;
+        self.insert_entry(key, entry).await
+    }
+
+    async fn retrieve_item(&self, key: &str) -> CacheResult<Option<T>> {
+        Ok(self.retrieve_versioned(key).await?.map(|(value, _)| value))
+    }
+
+    async fn retrieve_versioned(&self, key: &str) -> CacheResult<Option<(T, u64)>> {
+        Ok(self
+            .retrieve_entry(key)
+            .await?
+            .map(|entry| (entry.value, entry.version)))
+    }
+
+    async fn retrieve_entry(&self, key: &str) -> CacheResult<Option<EntryInfo<T>>> {
+        let policy = self.shard_config.policy.as_str();
+        let shard = self.shard(key);
+        if let Some(found) = shard.read().await.peek(key, policy) {
+            return Ok(found);
+        }
+        Ok(shard.write().await.lookup(key, policy))
+    }
+
+    /// Checks `condition` and writes under the same shard lock.
+    async fn write_item(
+        &self,
+        key: String,
+        value: T,
+        ttl: Option<u64>,
+        condition: Precondition,
+        tags: &[String],
+        sliding: bool,
+    ) -> CacheResult<Option<u64>> {
+        let now = Instant::now();
+        let mut store = self.shard(&key).write().await;
+        let current = store
+            .entries
+            .get(&key)
+            .filter(|entry| now < entry.expiry)
+            .map(|entry| entry.version);
+        let allowed = match condition {
+            Precondition::Always => true,
+            Precondition::Absent => current.is_none(),
+            Precondition::Present => current.is_some(),
+            Precondition::Version(version) => current == Some(version),
+        };
+        if !allowed {
+            return Ok(None);
+        }
+        let ttl = ttl.map(ttl_duration);
+        let expiry = now + ttl.unwrap_or(NO_EXPIRY);
+        let mut entry = self.new_entry(&key, value, expiry, tags.to_vec())?;
+        entry.sliding = ttl.filter(|_| sliding);
+        let version = entry.version;
+        let (evicted, kept) = store.insert_within_limits(&self.shard_config, key.clone(), entry);
+        EVICTION_COUNTER.inc_by(evicted as f64);
+        if !kept {
+            return Err(not_kept(&key));
+        }
+        Ok(Some(version))
+    }
+
+    /// Reads, adds and writes back under the shard lock. Each increment gives
+    /// the entry a new version.
+    async fn increment(&self, key: &str, delta: i64, ttl: Option<u64>) -> CacheResult<i64> {
+        let now = Instant::now();
+        let mut store = self.shard(key).write().await;
+        let (previous, expiry, tags, sliding) =
+            match store.entries.get(key).filter(|entry| now < entry.expiry) {
+                Some(entry) => (
+                    Some(entry.value.clone()),
+                    entry.expiry,
+                    entry.tags.clone(),
+                    entry.sliding,
+                ),
+                None => (
+                    None,
+                    now + ttl.map_or(NO_EXPIRY, ttl_duration),
+                    Vec::new(),
+                    None,
+                ),
+            };
+        let current = match &previous {
+            Some(value) => counter::decode(value).ok_or(CounterError::NotAnInteger)?,
+            None => 0,
+        };
+        let result = current.checked_add(delta).ok_or(CounterError::Overflow)?;
+        let value = match &previous {
+            Some(value) => counter::replace(value, result),
+            None => counter::encode(result),
+        }
+        .ok_or(CounterError::NotAnInteger)?;
+        let mut entry = self.new_entry(key, value, expiry, tags)?;
+        entry.sliding = sliding;
+        let (evicted, kept) =
+            store.insert_within_limits(&self.shard_config, key.to_string(), entry);
+        EVICTION_COUNTER.inc_by(evicted as f64);
+        if !kept {
+            return Err(not_kept(key));
+        }
+        Ok(result)
+    }
+
+    async fn retrieve_ttl(&self, key: &str) -> CacheResult<Option<Option<u64>>> {
+        let now = Instant::now();
+        let store = self.shard(key).read().await;
+        Ok(store
+            .entries
+            .get(key)
+            .filter(|entry| now < entry.expiry)
+            .map(|entry| remaining_ttl(entry.expiry, now)))
+    }
+
+    /// TTLs too long to tell apart from `NO_EXPIRY` count as never expiring.
+    async fn set_expiry(&self, key: &str, expiry: Expiry) -> CacheResult<bool> {
+        let now = Instant::now();
+        let ttl = match expiry {
+            Expiry::In(secs) => Duration::from_secs(secs),
+            Expiry::At(secs) => {
+                Duration::from_millis(secs.saturating_mul(1000).saturating_sub(now_millis()))
+            }
+            Expiry::Never => NO_EXPIRY,
+        };
+        let deadline = now + if ttl < NO_EXPIRY / 2 { ttl } else { NO_EXPIRY };
+        Ok(self.shard(key).write().await.set_expiry(key, deadline, now))
+    }
+
+    /// Walks each shard's ordered key index from the cursor, which is the
+    /// last key of the previous page. Each shard lock is held for at most
+    /// `limit + 1` live keys and `SCAN_SKIP_BUDGET` expired ones. A shard
+    /// that runs out of budget ends the page early, at the last key it
+    /// reached, so a page may hold fewer keys than `limit`.
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> CacheResult<KeyPage> {
+        let start = match cursor {
+            Some(cursor) if cursor >= prefix => Bound::Excluded(cursor),
+            _ => Bound::Included(prefix),
+        };
+        let now = Instant::now();
+        let mut keys = Vec::new();
+        // Every key up to here has been seen in every shard.
+        let mut reached: Option<String> = None;
+        for shard in self.shards.iter() {
+            let store = shard.read().await;
+            let (mut live, mut skipped) = (0, 0);
+            for key in store
+                .keys
+                .range::<str, _>((start, Bound::Unbounded))
+                .take_while(|key| key.starts_with(prefix))
+            {
+                let expiry = store.entries[key].expiry;
+                if now < expiry {
+                    keys.push(KeyInfo {
+                        key: key.clone(),
+                        ttl: remaining_ttl(expiry, now),
+                    });
+                    live += 1;
+                    if live > limit {
+                        break;
+                    }
+                } else {
+                    skipped += 1;
+                    if skipped == SCAN_SKIP_BUDGET {
+                        if reached.as_ref().is_none_or(|reached| key < reached) {
+                            reached = Some(key.clone());
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(reached) = &reached {
+            keys.retain(|info| info.key <= *reached);
+        }
+        keys.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        let cursor = if keys.len() > limit {
+            keys.truncate(limit);
+            keys.last().map(|last| last.key.clone())
+        } else {
+            reached
+        };
+        Ok(KeyPage { keys, cursor })
+    }
+
+    /// Walks each shard's ordered key index from the pattern's literal prefix,
+    /// `SWEEP_BATCH` keys per lock acquisition, yielding between batches.
+    async fn remove_matching(&self, pattern: &KeyPattern) -> CacheResult<u64> {
+        let prefix = pattern.literal_prefix();
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let mut resume: Option<String> = None;
+            loop {
+                let mut store = shard.write().await;
+                let start = match &resume {
+                    Some(key) => Bound::Excluded(key.as_str()),
+                    None => Bound::Included(prefix.as_str()),
+                };
+                let mut batch: Vec<String> = store
+                    .keys
+                    .range::<str, _>((start, Bound::Unbounded))
+                    .take_while(|key| key.starts_with(prefix.as_str()))
+                    .take(SWEEP_BATCH)
+                    .cloned()
+                    .collect();
+                let now = Instant::now();
+                for key in batch.iter().filter(|key| pattern.matches(key)) {
+                    if store.remove(key).is_some_and(|entry| now < entry.expiry) {
+                        removed += 1;
+                    }
+                }
+                drop(store);
+                if batch.len() < SWEEP_BATCH {
+                    break;
+                }
+                resume = batch.pop();
+                task::yield_now().await;
+            }
+        }
+        Ok(removed)
+    }
 
-    async fn retrieve_item(&self, key: &str) -> Option<T> {
-        let store = self.store.read().await;
-        if let Some((value, expiry)) = store.get(key) {
-            if Instant::now() < *expiry {
-                return Some(value.clone());
+    /// Takes the tag's keys out of each shard's index, then removes them
+    /// `SWEEP_BATCH` per lock acquisition, yielding between batches.
+    async fn invalidate_tag(&self, tag: &str) -> CacheResult<u64> {
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let Some(keys) = shard.write().await.tagged.remove(tag) else {
+                continue;
+            };
+            let keys: Vec<String> = keys.into_iter().collect();
+            for batch in keys.chunks(SWEEP_BATCH) {
+                let now = Instant::now();
+                let mut store = shard.write().await;
+                for key in batch {
+                    // The entry may have been rewritten without the tag since.
+                    let tagged = store
+                        .entries
+                        .get(key)
+                        .is_some_and(|entry| entry.tags.iter().any(|t| t == tag));
+                    if tagged && store.remove(key).is_some_and(|entry| now < entry.expiry) {
+                        removed += 1;
+                    }
+                }
+                drop(store);
+                task::yield_now().await;
             }
         }
-        None
+        Ok(removed)
     }
 
-    async fn remove_item(&self, key: &str) -> io::Result<()> {
-        let mut store = self.store.write().await;
+    async fn remove_item(&self, key: &str) -> CacheResult<()> {
+        let mut store = self.shard(key).write().await;
         store.remove(key);
         Ok(())
     }
 
-    async fn invalidate_expired(&self, interval: Duration) {
+    async fn retrieve_items(&self, keys: &[String]) -> Vec<CacheResult<Option<T>>> {
+        let policy = self.shard_config.policy.as_str();
+        let mut values: Vec<_> = keys.iter().map(|_| Ok(None)).collect();
+        for (shard, positions) in self.group_by_shard(keys.iter().map(String::as_str)) {
+            let mut deferred = Vec::new();
+            let store = self.shards[shard].read().await;
+            for position in positions {
+                match store.peek(&keys[position], policy) {
+                    Some(found) => values[position] = Ok(found.map(|entry| entry.value)),
+                    None => deferred.push(position),
+                }
+            }
+            drop(store);
+            if deferred.is_empty() {
+                continue;
+            }
+            let mut store = self.shards[shard].write().await;
+            for position in deferred {
+                values[position] = Ok(store
+                    .lookup(&keys[position], policy)
+                    .map(|entry| entry.value));
+            }
+        }
+        values
+    }
+
+    async fn insert_items(&self, items: Vec<(String, T, u64)>) -> Vec<CacheResult<()>> {
+        let now = Instant::now();
+        let groups = self.group_by_shard(items.iter().map(|(key, _, _)| key.as_str()));
+        let mut results: Vec<CacheResult<()>> = items.iter().map(|_| Ok(())).collect();
+        let mut items: Vec<_> = items.into_iter().map(Some).collect();
+        let mut evicted = 0;
+        for (shard, positions) in groups {
+            let mut store = self.shards[shard].write().await;
+            for position in positions {
+                let Some((key, value, ttl)) = items[position].take() else {
+                    continue;
+                };
+                match self.new_entry(&key, value, now + ttl_duration(ttl), Vec::new()) {
+                    Ok(entry) => {
+                        let (count, kept) =
+                            store.insert_within_limits(&self.shard_config, key.clone(), entry);
+                        evicted += count;
+                        if !kept {
+                            results[position] = Err(not_kept(&key));
+                        }
+                    }
+                    Err(e) => results[position] = Err(e),
+                }
+            }
+        }
+        EVICTION_COUNTER.inc_by(evicted as f64);
+        results
+    }
+
+    async fn remove_items(&self, keys: &[String]) -> Vec<CacheResult<()>> {
+        for (shard, positions) in self.group_by_shard(keys.iter().map(String::as_str)) {
+            let mut store = self.shards[shard].write().await;
+            for position in positions {
+                store.remove(&keys[position]);
+            }
+        }
+        keys.iter().map(|_| Ok(())).collect()
+    }
+
+    /// Removes due keys in batches of `SWEEP_BATCH`, releasing the shard lock
+    /// between batches, and stops once `SWEEP_BUDGET` is spent. The next pass
+    /// resumes with the shard where this one stopped.
+    async fn invalidate_expired(&self) {
+        let started = Instant::now();
+        let mut cursor = self.sweep_cursor.load(Ordering::Relaxed) % self.shards.len();
+        for _ in 0..self.shards.len() {
+            let shard = &self.shards[cursor];
+            while shard
+                .write()
+                .await
+                .remove_expired(Instant::now(), SWEEP_BATCH)
+            {
+                if started.elapsed() >= SWEEP_BUDGET {
+                    break;
+                }
+                task::yield_now().await;
+            }
+            if started.elapsed() >= SWEEP_BUDGET {
+                break;
+            }
+            cursor = (cursor + 1) % self.shards.len();
+        }
+        self.sweep_cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    async fn persist(&self) -> CacheResult<()> {
+        let Some(path) = &self.snapshot_path else {
+            return Ok(());
+        };
+        let count = self.save_snapshot(path).await?;
+        info!("Saved {} entries to snapshot {}", count, path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn bounded(max_entries: Option<usize>, max_bytes: Option<usize>) -> InMemoryCache<String> {
+        InMemoryCache::with_config(InMemoryConfig {
+            max_entries,
+            max_bytes,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used_entry() {
+        let cache = bounded(Some(2), None);
+        cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+        cache.insert_item("b".into(), "2".into(), 60).await.unwrap();
+
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        cache.insert_item("c".into(), "3".into(), 60).await.unwrap();
+
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        assert_eq!(cache.retrieve_item("b").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("c").await.unwrap(),
+            Some("3".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evicts_to_stay_within_memory_budget() {
+        let cache = bounded(None, Some(10));
+        cache
+            .insert_item("a".into(), "1234".into(), 60)
+            .await
+            .unwrap();
+        cache
+            .insert_item("b".into(), "1234".into(), 60)
+            .await
+            .unwrap();
+        cache
+            .insert_item("c".into(), "1234".into(), 60)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.retrieve_item("a").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("b").await.unwrap(),
+            Some("1234".to_string())
+        );
+        assert_eq!(
+            cache.retrieve_item("c").await.unwrap(),
+            Some("1234".to_string())
+        );
+        assert_eq!(cache.shards[0].read().await.bytes, 10);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_item_larger_than_memory_budget() {
+        let cache = bounded(None, Some(4));
+        let err = cache
+            .insert_item("key".into(), "value".into(), 60)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, CacheError::TooLarge(_)));
+        assert!(cache.shards[0].read().await.entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_replaces_size_accounting() {
+        let cache = bounded(Some(10), Some(100));
+        cache
+            .insert_item("a".into(), "12345".into(), 60)
+            .await
+            .unwrap();
+        cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+
+        let store = cache.shards[0].read().await;
+        assert_eq!(store.entries.len(), 1);
+        assert_eq!(store.bytes, 2);
+    }
+
+    #[tokio::test]
+    async fn test_tinylfu_keeps_hot_entry_over_one_off_writes() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            max_entries: Some(1),
+            policy: EvictionPolicyKind::TinyLfu,
+            ..Default::default()
+        });
+        cache
+            .insert_item("hot".into(), "1".into(), 60)
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            cache.retrieve_item("hot").await.unwrap();
+        }
+        let rejected = cache.insert_item("scan".into(), "2".into(), 60).await;
+        assert!(matches!(rejected, Err(CacheError::NotAdmitted(_))));
+
+        assert_eq!(
+            cache.retrieve_item("hot").await.unwrap(),
+            Some("1".to_string())
+        );
+        assert_eq!(cache.retrieve_item("scan").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_writes_rejected_by_admission_fail() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            max_entries: Some(1),
+            policy: EvictionPolicyKind::TinyLfu,
+            ..Default::default()
+        });
+        cache
+            .insert_item("hot".into(), "1".into(), 60)
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            cache.retrieve_item("hot").await.unwrap();
+        }
+
+        let written = cache
+            .write_item(
+                "new".into(),
+                "2".into(),
+                Some(60),
+                Precondition::Always,
+                &[],
+                false,
+            )
+            .await;
+        assert!(matches!(written, Err(CacheError::NotAdmitted(_))));
+        assert_eq!(cache.retrieve_item("new").await.unwrap(), None);
+
+        let counted = cache.increment("counter", 1, Some(60)).await;
+        assert!(matches!(counted, Err(CacheError::NotAdmitted(_))));
+        assert_eq!(cache.retrieve_item("counter").await.unwrap(), None);
+
+        let results = cache
+            .insert_items(vec![("batched".into(), "3".into(), 60)])
+            .await;
+        assert!(matches!(results[0], Err(CacheError::NotAdmitted(_))));
+        assert_eq!(
+            cache.retrieve_item("hot").await.unwrap(),
+            Some("1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overwrites_skip_admission() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            max_bytes: Some(16),
+            shards: 1,
+            policy: EvictionPolicyKind::TinyLfu,
+            ..Default::default()
+        });
+        cache
+            .insert_item("cold".into(), "a".into(), 60)
+            .await
+            .unwrap();
+        cache
+            .insert_item("hot".into(), "b".into(), 60)
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            cache.retrieve_item("hot").await.unwrap();
+        }
+
+        // Growing "cold" overflows the budget. Admission would prefer "hot"
+        // and drop the overwrite; instead "hot" makes room.
+        let version = cache
+            .write_item(
+                "cold".into(),
+                "cccccccccc".into(),
+                Some(60),
+                Precondition::Always,
+                &[],
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(version.is_some());
+        assert_eq!(
+            cache.retrieve_item("cold").await.unwrap(),
+            Some("cccccccccc".to_string())
+        );
+        assert_eq!(cache.retrieve_item("hot").await.unwrap(), None);
+        assert_eq!(cache.increment("cold", 1, None).await.ok(), None);
+        cache
+            .insert_item("cold".into(), "7".into(), 60)
+            .await
+            .unwrap();
+        assert_eq!(cache.increment("cold", 1, None).await, Ok(8));
+    }
+
+    #[tokio::test]
+    async fn test_limits_are_split_across_shards() {
+        let cache: InMemoryCache<String> = InMemoryCache::with_config(InMemoryConfig {
+            max_entries: Some(64),
+            max_bytes: Some(6400),
+            shards: 8,
+            ..Default::default()
+        });
+        assert_eq!(cache.shards.len(), 8);
+        assert_eq!(cache.shard_config.max_entries, Some(8));
+        assert_eq!(cache.shard_config.max_bytes, Some(800));
+
+        for i in 0..1000 {
+            cache
+                .insert_item(format!("key{}", i), "value".into(), 60)
+                .await
+                .unwrap();
+        }
+        for shard in cache.shards.iter() {
+            let store = shard.read().await;
+            assert!(!store.entries.is_empty());
+            assert!(store.entries.len() <= 8);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writers_on_shards() {
+        let cache: Arc<InMemoryCache<String>> =
+            Arc::new(InMemoryCache::with_config(InMemoryConfig {
+                shards: 4,
+                ..Default::default()
+            }));
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    for i in 0..100 {
+                        let key = format!("{}-{}", writer, i);
+                        cache.insert_item(key, i.to_string(), 60).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        assert_eq!(
+            cache.retrieve_item("7-99").await.unwrap(),
+            Some("99".to_string())
+        );
+        let mut total = 0;
+        for shard in cache.shards.iter() {
+            total += shard.read().await.entries.len();
+        }
+        assert_eq!(total, 800);
+    }
+
+    #[tokio::test]
+    async fn test_batches_keep_request_order_across_shards() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            max_bytes: Some(160),
+            shards: 4,
+            ..Default::default()
+        });
+        let items = (0..6)
+            .map(|i| (format!("key{}", i), i.to_string(), 60))
+            .chain([("big".to_string(), "x".repeat(40), 60)])
+            .collect();
+
+        let results = cache.insert_items(items).await;
+        assert!(results[..6].iter().all(|result| result.is_ok()));
+        assert!(matches!(results[6], Err(CacheError::TooLarge(_))));
+
+        let keys: Vec<String> = ["key5", "missing", "key0", "big"].map(String::from).into();
+        assert_eq!(
+            cache.retrieve_items(&keys).await,
+            vec![
+                Ok(Some("5".to_string())),
+                Ok(None),
+                Ok(Some("0".to_string())),
+                Ok(None)
+            ]
+        );
+
+        assert!(cache
+            .remove_items(&keys)
+            .await
+            .iter()
+            .all(|result| result.is_ok()));
+        assert_eq!(cache.retrieve_item("key5").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("key1").await.unwrap(),
+            Some("1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clear_empties_every_shard() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            max_entries: Some(8),
+            shards: 4,
+            ..Default::default()
+        });
+        for i in 0..8 {
+            cache
+                .insert_item(format!("key{}", i), "v".to_string(), 60)
+                .await
+                .unwrap();
+        }
+
+        cache.clear().await;
+        for i in 0..8 {
+            assert_eq!(
+                cache.retrieve_item(&format!("key{}", i)).await.unwrap(),
+                None
+            );
+        }
+        for shard in cache.shards.iter() {
+            assert_eq!(shard.read().await.bytes, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conditional_writes() {
+        let cache = InMemoryCache::new();
+        let write = |value: &str, condition| {
+            cache.write_item(
+                "key".to_string(),
+                value.to_string(),
+                Some(60),
+                condition,
+                &[],
+                false,
+            )
+        };
+
+        assert_eq!(write("a", Precondition::Present).await.unwrap(), None);
+        let first = write("a", Precondition::Absent).await.unwrap().unwrap();
+        assert_eq!(write("b", Precondition::Absent).await.unwrap(), None);
+        assert_eq!(
+            write("b", Precondition::Version(first + 1)).await.unwrap(),
+            None
+        );
+        let second = write("b", Precondition::Version(first))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(second > first);
+        assert_eq!(
+            cache.retrieve_versioned("key").await.unwrap(),
+            Some(("b".to_string(), second))
+        );
+        assert_eq!(
+            write("c", Precondition::Version(first)).await.unwrap(),
+            None
+        );
+        assert!(write("c", Precondition::Present).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_increment() {
+        let cache = InMemoryCache::new();
+        assert_eq!(cache.increment("hits", 5, Some(60)).await.unwrap(), 5);
+        assert_eq!(cache.increment("hits", -2, None).await.unwrap(), 3);
+        assert_eq!(
+            cache.retrieve_item("hits").await.unwrap(),
+            Some("3".to_string())
+        );
+
+        cache
+            .insert_item("name".to_string(), "abc".to_string(), 60)
+            .await
+            .unwrap();
+        let err = cache.increment("name", 1, None).await.unwrap_err();
+        assert_eq!(err, CacheError::Counter(CounterError::NotAnInteger));
+
+        cache
+            .insert_item("max".to_string(), i64::MAX.to_string(), 60)
+            .await
+            .unwrap();
+        let err = cache.increment("max", 1, None).await.unwrap_err();
+        assert_eq!(err, CacheError::Counter(CounterError::Overflow));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_increments_are_not_lost() {
+        let cache = Arc::new(InMemoryCache::<String>::new());
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        cache.increment("views", 1, Some(60)).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(
+            cache.retrieve_item("views").await.unwrap(),
+            Some("800".to_string())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_increment_keeps_ttl() {
+        let cache = InMemoryCache::<String>::new();
+        cache.increment("window", 1, Some(10)).await.unwrap();
+        tokio::time::advance(Duration::from_secs(6)).await;
+        cache.increment("window", 1, Some(10)).await.unwrap();
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert_eq!(cache.retrieve_item("window").await.unwrap(), None);
+        assert_eq!(cache.increment("window", 1, Some(10)).await.unwrap(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retrieve_entry_reports_remaining_ttl() {
+        let cache = InMemoryCache::<String>::new();
+        cache.insert_item("a".into(), "v".into(), 10).await.unwrap();
+        cache.increment("b", 1, None).await.unwrap();
+        tokio::time::advance(Duration::from_secs(4)).await;
+
+        let entry = cache.retrieve_entry("a").await.unwrap().unwrap();
+        assert_eq!((entry.value.as_str(), entry.ttl), ("v", Some(6)));
+        assert!(entry.version > 0);
+        assert_eq!(cache.retrieve_entry("b").await.unwrap().unwrap().ttl, None);
+        assert_eq!(cache.retrieve_entry("c").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_scan_keys_pages_through_prefix_in_order() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            shards: 4,
+            ..Default::default()
+        });
+        for i in 0..25 {
+            cache
+                .insert_item(format!("user:{:02}", i), "v".to_string(), 60)
+                .await
+                .unwrap();
+        }
+        cache
+            .insert_item("session:1".to_string(), "v".to_string(), 60)
+            .await
+            .unwrap();
+        cache.remove_item("user:07").await.unwrap();
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = cache
+                .scan_keys("user:", cursor.as_deref(), 10)
+                .await
+                .unwrap();
+            assert!(page.keys.len() <= 10);
+            listed.extend(page.keys);
+            match page.cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        let expected: Vec<String> = (0..25)
+            .filter(|i| *i != 7)
+            .map(|i| format!("user:{:02}", i))
+            .collect();
+        assert_eq!(
+            listed
+                .iter()
+                .map(|info| info.key.clone())
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert!(listed.iter().all(|info| info.ttl == Some(60)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scan_keys_skips_expired_entries() {
+        let cache = InMemoryCache::new();
+        cache
+            .insert_item("a".to_string(), "v".to_string(), 1)
+            .await
+            .unwrap();
+        cache
+            .insert_item("b".to_string(), "v".to_string(), 60)
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_millis(1500)).await;
+
+        let page = cache.scan_keys("", None, 10).await.unwrap();
+        assert_eq!(
+            page.keys,
+            vec![KeyInfo {
+                key: "b".to_string(),
+                ttl: Some(59),
+            }]
+        );
+        assert_eq!(page.cursor, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scan_keys_bounds_expired_entries_per_call() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            shards: 2,
+            ..Default::default()
+        });
+        for i in 0..3 * SCAN_SKIP_BUDGET {
+            cache
+                .insert_item(format!("k:{:04}", i), "v".to_string(), 1)
+                .await
+                .unwrap();
+        }
+        cache
+            .insert_item("k:live".to_string(), "v".to_string(), 60)
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_millis(1500)).await;
+
+        let mut pages = 0;
+        let mut listed = Vec::new();
+        let mut cursor = None;
         loop {
-            time::sleep(interval).await;
-            let mut store = self.store.write().await;
-            let now = Instant::now();
-            store.retain(|_, (_, expiry)| *expiry > now);
+            let page = cache.scan_keys("k:", cursor.as_deref(), 10).await.unwrap();
+            pages += 1;
+            listed.extend(page.keys.into_iter().map(|info| info.key));
+            match page.cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert!(pages > 1);
+        assert_eq!(listed, vec!["k:live".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_remove_matching_spans_batches() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            shards: 2,
+            ..Default::default()
+        });
+        for i in 0..1000 {
+            cache
+                .insert_item(format!("user:{}:profile", i), "v".to_string(), 60)
+                .await
+                .unwrap();
+            cache
+                .insert_item(format!("user:{}:name", i), "v".to_string(), 60)
+                .await
+                .unwrap();
+        }
+        cache
+            .insert_item("catalog:v7:1".to_string(), "v".to_string(), 60)
+            .await
+            .unwrap();
+
+        let profiles = KeyPattern::Glob("user:*:profile".to_string());
+        assert_eq!(cache.remove_matching(&profiles).await.unwrap(), 1000);
+        assert_eq!(cache.retrieve_item("user:5:profile").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("user:5:name").await.unwrap(),
+            Some("v".to_string())
+        );
+
+        let catalog = KeyPattern::Prefix("catalog:v7:".to_string());
+        assert_eq!(cache.remove_matching(&catalog).await.unwrap(), 1);
+        assert_eq!(cache.remove_matching(&catalog).await.unwrap(), 0);
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_tag() {
+        let cache = InMemoryCache::with_config(InMemoryConfig {
+            shards: 2,
+            ..Default::default()
+        });
+        for (key, carried) in [
+            ("view:a", tags(&["user:1", "feed"])),
+            ("view:b", tags(&["user:1"])),
+            ("view:c", tags(&["feed"])),
+        ] {
+            cache
+                .write_item(
+                    key.to_string(),
+                    "v".to_string(),
+                    Some(60),
+                    Precondition::Always,
+                    &carried,
+                    false,
+                )
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(cache.invalidate_tag("user:1").await.unwrap(), 2);
+        assert_eq!(cache.retrieve_item("view:a").await.unwrap(), None);
+        assert_eq!(cache.retrieve_item("view:b").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("view:c").await.unwrap(),
+            Some("v".to_string())
+        );
+        assert_eq!(cache.invalidate_tag("user:1").await.unwrap(), 0);
+        assert_eq!(cache.invalidate_tag("feed").await.unwrap(), 1);
+        for shard in cache.shards.iter() {
+            assert!(shard.read().await.tagged.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_drops_old_tags() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        cache
+            .write_item(
+                "key".into(),
+                "a".into(),
+                Some(60),
+                Precondition::Always,
+                &tags(&["old", "kept"]),
+                false,
+            )
+            .await
+            .unwrap();
+        cache
+            .write_item(
+                "key".into(),
+                "b".into(),
+                Some(60),
+                Precondition::Always,
+                &tags(&["kept"]),
+                false,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(cache.invalidate_tag("old").await.unwrap(), 0);
+        assert_eq!(
+            cache.retrieve_item("key").await.unwrap(),
+            Some("b".to_string())
+        );
+        cache.increment("n", 1, None).await.unwrap();
+        assert_eq!(cache.invalidate_tag("kept").await.unwrap(), 1);
+        assert_eq!(cache.retrieve_item("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_cleans_tag_index() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        cache
+            .write_item(
+                "short".into(),
+                "v".into(),
+                Some(0),
+                Precondition::Always,
+                &tags(&["t"]),
+                false,
+            )
+            .await
+            .unwrap();
+        cache
+            .write_item(
+                "long".into(),
+                "v".into(),
+                Some(60),
+                Precondition::Always,
+                &tags(&["t"]),
+                false,
+            )
+            .await
+            .unwrap();
+
+        cache.invalidate_expired().await;
+        {
+            let store = cache.shards[0].read().await;
+            assert_eq!(store.tagged["t"].len(), 1);
+        }
+        cache.remove_item("long").await.unwrap();
+        assert!(cache.shards[0].read().await.tagged.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_entry_without_ttl_never_expires() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        cache
+            .write_item(
+                "key".into(),
+                "v".into(),
+                None,
+                Precondition::Always,
+                &[],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(cache.retrieve_ttl("key").await.unwrap(), Some(None));
+
+        tokio::time::advance(Duration::from_secs(1_000_000)).await;
+        cache.invalidate_expired().await;
+        assert_eq!(
+            cache.retrieve_item("key").await.unwrap(),
+            Some("v".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_huge_ttls_are_capped() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        cache
+            .insert_item("a".into(), "v".into(), u64::MAX)
+            .await
+            .unwrap();
+        cache
+            .write_item(
+                "b".into(),
+                "v".into(),
+                Some(u64::MAX),
+                Precondition::Always,
+                &[],
+                true,
+            )
+            .await
+            .unwrap();
+        cache.increment("c", 1, Some(u64::MAX)).await.unwrap();
+        assert_eq!(cache.retrieve_item("b").await.unwrap(), Some("v".into()));
+        for key in ["a", "b", "c"] {
+            assert!(cache.retrieve_ttl(key).await.unwrap().is_some());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_set_expiry() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        cache
+            .insert_item("key".into(), "v".into(), 10)
+            .await
+            .unwrap();
+        let (_, version) = cache.retrieve_versioned("key").await.unwrap().unwrap();
+
+        assert!(cache.set_expiry("key", Expiry::In(100)).await.unwrap());
+        assert_eq!(cache.retrieve_ttl("key").await.unwrap(), Some(Some(100)));
+        tokio::time::advance(Duration::from_secs(50)).await;
+        assert_eq!(cache.retrieve_ttl("key").await.unwrap(), Some(Some(50)));
+
+        assert!(cache.set_expiry("key", Expiry::Never).await.unwrap());
+        assert_eq!(cache.retrieve_ttl("key").await.unwrap(), Some(None));
+        tokio::time::advance(Duration::from_secs(1000)).await;
+        cache.invalidate_expired().await;
+        assert_eq!(
+            cache.retrieve_versioned("key").await.unwrap(),
+            Some(("v".to_string(), version))
+        );
+
+        let at = now_millis() / 1000 + 30;
+        assert!(cache.set_expiry("key", Expiry::At(at)).await.unwrap());
+        assert!(matches!(
+            cache.retrieve_ttl("key").await.unwrap(),
+            Some(Some(29..=30))
+        ));
+        assert!(cache.set_expiry("key", Expiry::At(0)).await.unwrap());
+        assert_eq!(cache.retrieve_ttl("key").await.unwrap(), None);
+        assert!(!cache.set_expiry("key", Expiry::In(10)).await.unwrap());
+        assert!(!cache.shards[0].read().await.keys.contains("key"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shortened_ttl_is_swept() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        cache
+            .insert_item("key".into(), "v".into(), 60)
+            .await
+            .unwrap();
+        assert!(cache.set_expiry("key", Expiry::In(1)).await.unwrap());
+        tokio::time::advance(Duration::from_secs(2)).await;
+
+        cache.invalidate_expired().await;
+        assert!(cache.shards[0].read().await.entries.is_empty());
+    }
+
+    async fn write_sliding(cache: &InMemoryCache<String>, key: &str, ttl: u64) {
+        cache
+            .write_item(
+                key.into(),
+                "v".into(),
+                Some(ttl),
+                Precondition::Always,
+                &[],
+                true,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sliding_entry_lives_while_read() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        write_sliding(&cache, "session", 10).await;
+
+        for _ in 0..3 {
+            tokio::time::advance(Duration::from_secs(8)).await;
+            cache.invalidate_expired().await;
+            assert_eq!(
+                cache.retrieve_item("session").await.unwrap(),
+                Some("v".to_string())
+            );
+        }
+        assert_eq!(cache.retrieve_ttl("session").await.unwrap(), Some(Some(10)));
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert_eq!(cache.retrieve_item("session").await.unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sweeper_requeues_slid_deadline() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        write_sliding(&cache, "session", 10).await;
+        tokio::time::advance(Duration::from_secs(8)).await;
+        cache.retrieve_item("session").await.unwrap();
+        assert_eq!(cache.shards[0].read().await.deadlines.len(), 1);
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        cache.invalidate_expired().await;
+        {
+            let store = cache.shards[0].read().await;
+            assert!(store.entries.contains_key("session"));
+            assert_eq!(store.deadlines.len(), 1);
+        }
+        tokio::time::advance(Duration::from_secs(6)).await;
+        cache.invalidate_expired().await;
+        assert!(cache.shards[0].read().await.entries.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_set_expiry_stops_sliding() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        write_sliding(&cache, "session", 10).await;
+        assert!(cache.set_expiry("session", Expiry::In(5)).await.unwrap());
+
+        tokio::time::advance(Duration::from_secs(4)).await;
+        cache.retrieve_item("session").await.unwrap();
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(cache.retrieve_item("session").await.unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_entry_counts_as_absent() {
+        let cache = InMemoryCache::new();
+        cache
+            .insert_item("key".to_string(), "a".to_string(), 1)
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(2)).await;
+
+        let present = cache
+            .write_item(
+                "key".to_string(),
+                "b".to_string(),
+                Some(60),
+                Precondition::Present,
+                &[],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(present, None);
+        let absent = cache
+            .write_item(
+                "key".to_string(),
+                "b".to_string(),
+                Some(60),
+                Precondition::Absent,
+                &[],
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(absent.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_expiry_sweep_is_incremental() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        for i in 0..10 {
+            cache
+                .insert_item(format!("short{}", i), "v".into(), 0)
+                .await
+                .unwrap();
         }
+        cache
+            .insert_item("long".into(), "v".into(), 60)
+            .await
+            .unwrap();
+
+        let mut store = cache.shards[0].write().await;
+        let now = Instant::now();
+        assert!(store.remove_expired(now, 4));
+        assert_eq!(store.entries.len(), 7);
+        assert!(store.remove_expired(now, 4));
+        assert!(!store.remove_expired(now, 4));
+        assert_eq!(store.entries.len(), 1);
+        assert!(store.entries.contains_key("long"));
+        assert_eq!(store.deadlines.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stale_deadline_does_not_expire_rewritten_key() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        cache
+            .insert_item("key".into(), "old".into(), 0)
+            .await
+            .unwrap();
+        cache
+            .insert_item("key".into(), "new".into(), 60)
+            .await
+            .unwrap();
+
+        let mut store = cache.shards[0].write().await;
+        assert!(!store.remove_expired(Instant::now(), SWEEP_BATCH));
+        assert_eq!(store.entries["key"].value, "new");
+    }
+
+    #[tokio::test]
+    async fn test_overwrites_do_not_grow_deadline_heap() {
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        for i in 0..10 * SWEEP_BATCH {
+            cache
+                .insert_item("key".into(), i.to_string(), 60)
+                .await
+                .unwrap();
+        }
+
+        assert!(cache.shards[0].read().await.deadlines.len() <= 2 + SWEEP_BATCH);
+    }
+
+    fn temp_snapshot(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "cache_service_{}_{}_{}.json",
+            name,
+            std::process::id(),
+            now_millis()
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip_keeps_remaining_ttl() {
+        let path = temp_snapshot("round_trip");
+        let cache: InMemoryCache<String> = InMemoryCache::with_config(InMemoryConfig {
+            shards: 4,
+            snapshot_path: Some(path.clone()),
+            ..Default::default()
+        });
+        cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+        cache
+            .insert_item("b".into(), "2".into(), 3600)
+            .await
+            .unwrap();
+        cache
+            .insert_item("gone".into(), "3".into(), 0)
+            .await
+            .unwrap();
+        cache.persist().await.unwrap();
+
+        let restored: InMemoryCache<String> = InMemoryCache::new();
+        assert_eq!(restored.load_snapshot(&path).await.unwrap(), 2);
+        assert_eq!(
+            restored.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        assert_eq!(
+            restored.retrieve_item("b").await.unwrap(),
+            Some("2".to_string())
+        );
+        assert_eq!(restored.retrieve_item("gone").await.unwrap(), None);
+
+        let store = restored.shards[0].read().await;
+        let remaining = store.entries["b"].expiry - Instant::now();
+        assert!(remaining > Duration::from_secs(3590) && remaining <= Duration::from_secs(3600));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_skips_entries_expired_while_down() {
+        let path = temp_snapshot("expired");
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            saved_at: now_millis() - 10_000,
+            entries: vec![
+                SnapshotEntry {
+                    key: "stale".to_string(),
+                    value: "x".to_string(),
+                    ttl_ms: 5_000,
+                    version: 0,
+                    tags: Vec::new(),
+                    sliding_ms: None,
+                },
+                SnapshotEntry {
+                    key: "fresh".to_string(),
+                    value: "y".to_string(),
+                    ttl_ms: 60_000,
+                    version: 0,
+                    tags: Vec::new(),
+                    sliding_ms: None,
+                },
+            ],
+        };
+        fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        assert_eq!(cache.load_snapshot(&path).await.unwrap(), 1);
+        assert_eq!(cache.retrieve_item("stale").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("fresh").await.unwrap(),
+            Some("y".to_string())
+        );
+        let remaining = cache.shards[0].read().await.entries["fresh"].expiry - Instant::now();
+        assert!(remaining <= Duration::from_secs(50));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_rejects_unknown_version() {
+        let path = temp_snapshot("version");
+        fs::write(&path, r#"{"version":99,"saved_at":0,"entries":[]}"#).unwrap();
+
+        let cache: InMemoryCache<String> = InMemoryCache::new();
+        let err = cache.load_snapshot(&path).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,265 @@
+use super::in_memory_cache::{InMemoryCache, Weigh};
+use super::metrics::INVALIDATION_COUNTER;
+use super::now_millis;
+use super::redis_cache::RedisTopology;
+use super::schema::Cache;
+use futures_util::{Stream, StreamExt};
+use log::{info, warn};
+use redis::RedisResult;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{Arc, Weak};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+
+/// First delay before resubscribing after the subscription is lost.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound on the delay between resubscription attempts.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    static ref POD_ID: String = format!(
+        "{:016x}",
+        RandomState::new().hash_one((std::process::id(), now_millis()))
+    );
+}
+
+/// Identifies this process as the sender of the invalidations it publishes,
+/// so that its own subscription can skip them.
+pub fn pod_id() -> &'static str {
+    &POD_ID
+}
+
+/// An invalidation of `key` published by `sender`, as `<sender> <key>`. An
+/// empty `sender` reaches every pod, this one included.
+pub fn message(sender: &str, key: &str) -> String {
+    format!("{} {}", sender, key)
+}
+
+/// Subscribes to `channel`, on which `RedisCache::with_invalidation` publishes
+/// every written or removed key, and evicts those keys from `local`. Keys this
+/// process published itself are skipped: its writes already updated `local`.
+///
+/// While the subscription is down, messages are lost. `local` is therefore
+/// flushed whenever the subscription drops and again once it is back, and
+/// the task keeps resubscribing with exponential backoff. It exits once
+/// `local` has been dropped.
+pub fn subscribe<T>(
+    topology: RedisTopology,
+    channel: String,
+    local: &Arc<InMemoryCache<T>>,
+) -> JoinHandle<()>
+where
+    T: Weigh + Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    let name = channel.clone();
+    let connect = move || {
+        let (topology, channel) = (topology.clone(), channel.clone());
+        async move {
+            let mut pubsub = topology.pubsub().await?;
+            pubsub.subscribe(&channel).await?;
+            let payloads = pubsub.into_on_message().filter_map(|message| async move {
+                message
+                    .get_payload::<String>()
+                    .map_err(|e| warn!("Ignoring malformed invalidation: {}", e))
+                    .ok()
+            });
+            Ok(payloads.boxed())
+        }
+    };
+    tokio::spawn(run(connect, name, pod_id(), Arc::downgrade(local)))
+}
+
+/// Follows the payloads of each subscription `connect` opens, evicting the
+/// keys not sent by `own_id`.
+async fn run<T, C, F, S>(
+    mut connect: C,
+    channel: String,
+    own_id: &str,
+    local: Weak<InMemoryCache<T>>,
+) where
+    T: Weigh + Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    C: FnMut() -> F,
+    F: Future<Output = RedisResult<S>>,
+    S: Stream<Item = String> + Unpin,
+{
+    let mut backoff = RECONNECT_BACKOFF;
+    let mut connected_before = false;
+    loop {
+        match connect().await {
+            Ok(mut payloads) => {
+                info!("Subscribed to cache invalidations on {}", channel);
+                backoff = RECONNECT_BACKOFF;
+                if connected_before && !flush(&local, "resubscribed").await {
+                    return;
+                }
+                connected_before = true;
+
+                while let Some(payload) = payloads.next().await {
+                    let Some(local) = local.upgrade() else {
+                        return;
+                    };
+                    let Some((sender, key)) = payload.split_once(' ') else {
+                        warn!("Ignoring malformed invalidation: {:?}", payload);
+                        continue;
+                    };
+                    if sender == own_id {
+                        continue;
+                    }
+                    INVALIDATION_COUNTER.inc();
+                    if let Err(e) = local.remove_item(key).await {
+                        warn!("Failed to invalidate {}: {}", key, e);
+                    }
+                }
+                warn!("Lost the cache invalidation subscription on {}", channel);
+                if !flush(&local, "subscription lost").await {
+                    return;
+                }
+            }
+            Err(e) => warn!("Failed to subscribe to {}: {}", channel, e),
+        }
+        if local.strong_count() == 0 {
+            return;
+        }
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+    }
+}
+
+/// Empties the local tier, since invalidations may have been missed. Returns
+/// `false` once the cache is gone.
+async fn flush<T>(local: &Weak<InMemoryCache<T>>, reason: &str) -> bool {
+    let Some(local) = local.upgrade() else {
+        return false;
+    };
+    info!("Flushing local cache tier: {}", reason);
+    local.clear().await;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream::{self, BoxStream};
+    use redis::{ErrorKind, RedisError};
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc::{self, UnboundedSender};
+
+    type Payloads = BoxStream<'static, String>;
+
+    /// A connector handing out the subscriptions, or failures, queued in
+    /// `attempts`; once they run out, every attempt fails.
+    fn connector(
+        attempts: Vec<RedisResult<Payloads>>,
+    ) -> impl FnMut() -> std::future::Ready<RedisResult<Payloads>> {
+        let attempts = Mutex::new(VecDeque::from(attempts));
+        move || {
+            std::future::ready(
+                attempts
+                    .lock()
+                    .unwrap()
+                    .pop_front()
+                    .unwrap_or_else(|| Err(RedisError::from((ErrorKind::IoError, "unreachable")))),
+            )
+        }
+    }
+
+    fn subscription() -> (UnboundedSender<String>, RedisResult<Payloads>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let payloads = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|payload| (payload, receiver))
+        });
+        (sender, Ok(payloads.boxed()))
+    }
+
+    async fn filled(keys: &[&str]) -> Arc<InMemoryCache<String>> {
+        let local = Arc::new(InMemoryCache::new());
+        for key in keys {
+            local
+                .insert_item(key.to_string(), "v".to_string(), 60)
+                .await
+                .unwrap();
+        }
+        local
+    }
+
+    fn follow(
+        attempts: Vec<RedisResult<Payloads>>,
+        local: &Arc<InMemoryCache<String>>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(run(
+            connector(attempts),
+            "test".to_string(),
+            "me",
+            Arc::downgrade(local),
+        ))
+    }
+
+    async fn present(local: &InMemoryCache<String>, key: &str) -> bool {
+        local.retrieve_item(key).await.unwrap().is_some()
+    }
+
+    #[tokio::test]
+    async fn test_messages_evict_keys_not_sent_by_this_pod() {
+        let local = filled(&["a", "b", "c", "d e"]).await;
+        let (messages, attempt) = subscription();
+        let task = follow(vec![attempt], &local);
+
+        messages.send(message("other", "a")).unwrap();
+        messages.send(message("me", "b")).unwrap();
+        messages.send(message("", "c")).unwrap();
+        messages.send(message("other", "d e")).unwrap();
+        messages.send("malformed".to_string()).unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+
+        assert!(!present(&local, "a").await);
+        assert!(present(&local, "b").await);
+        assert!(!present(&local, "c").await);
+        assert!(!present(&local, "d e").await);
+        assert!(!task.is_finished());
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lost_subscription_flushes_and_resubscribes() {
+        let local = filled(&["a"]).await;
+        let (messages, first) = subscription();
+        let (resubscribed, second) = subscription();
+        let failure = Err(RedisError::from((ErrorKind::IoError, "refused")));
+        let task = follow(vec![first, failure, second], &local);
+
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(present(&local, "a").await);
+        drop(messages);
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(!present(&local, "a").await);
+
+        // Written while the subscription is down; dropped once it is back.
+        local.insert_item("b".into(), "v".into(), 60).await.unwrap();
+        time::sleep(RECONNECT_BACKOFF + RECONNECT_BACKOFF * 2).await;
+        assert!(!present(&local, "b").await);
+
+        local.insert_item("c".into(), "v".into(), 60).await.unwrap();
+        resubscribed.send(message("other", "c")).unwrap();
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(!present(&local, "c").await);
+        assert!(!task.is_finished());
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_exits_once_the_cache_is_dropped() {
+        let local = filled(&[]).await;
+        let (messages, attempt) = subscription();
+        let task = follow(vec![attempt], &local);
+        drop(local);
+        messages.send(message("other", "a")).unwrap();
+        time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
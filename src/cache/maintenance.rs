@@ -0,0 +1,271 @@
+use super::metrics::SWEEPER_UP;
+use super::schema::Cache;
+use log::{error, info};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SweeperState {
+    Running,
+    Stopped,
+    Failed,
+}
+
+impl SweeperState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => SweeperState::Running,
+            1 => SweeperState::Stopped,
+            _ => SweeperState::Failed,
+        }
+    }
+}
+
+/// Shared view of the sweeper's state, handed to the health endpoint.
+#[derive(Clone, Debug)]
+pub struct MaintenanceHealth {
+    state: Arc<AtomicU8>,
+}
+
+impl MaintenanceHealth {
+    fn new() -> Self {
+        let health = Self {
+            state: Arc::new(AtomicU8::new(0)),
+        };
+        health.set(SweeperState::Running);
+        health
+    }
+
+    fn set(&self, state: SweeperState) {
+        self.state.store(state as u8, Ordering::SeqCst);
+        SWEEPER_UP.set(i64::from(state == SweeperState::Running));
+    }
+
+    pub fn state(&self) -> SweeperState {
+        SweeperState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.state() == SweeperState::Running
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MaintenanceConfig {
+    pub sweep_interval: Duration,
+    /// How often to call `Cache::persist`; `None` only persists on shutdown.
+    pub persist_interval: Option<Duration>,
+}
+
+/// Background task that calls `Cache::invalidate_expired` and
+/// `Cache::persist` on their intervals, and `Cache::persist` once more when
+/// shut down.
+///
+/// The work runs in its own task under a supervisor, so a panicking pass
+/// marks the health as `Failed` instead of silently disappearing.
+pub struct Maintenance {
+    token: CancellationToken,
+    interval: watch::Sender<Duration>,
+    supervisor: JoinHandle<()>,
+    health: MaintenanceHealth,
+}
+
+impl Maintenance {
+    pub fn start<T: 'static>(cache: Arc<dyn Cache<T>>, config: MaintenanceConfig) -> Self {
+        let token = CancellationToken::new();
+        let (interval_tx, interval_rx) = watch::channel(config.sweep_interval);
+        let health = MaintenanceHealth::new();
+
+        let worker = tokio::spawn(run(
+            cache,
+            interval_rx,
+            config.persist_interval,
+            token.clone(),
+        ));
+        let supervisor_health = health.clone();
+        let supervisor = tokio::spawn(async move {
+            match worker.await {
+                Ok(()) => {
+                    info!("Cache sweeper stopped");
+                    supervisor_health.set(SweeperState::Stopped);
+                }
+                Err(e) => {
+                    error!("Cache sweeper died: {}", e);
+                    supervisor_health.set(SweeperState::Failed);
+                }
+            }
+        });
+
+        Self {
+            token,
+            interval: interval_tx,
+            supervisor,
+            health,
+        }
+    }
+
+    pub fn health(&self) -> MaintenanceHealth {
+        self.health.clone()
+    }
+
+    /// Changes the sweep interval; the new value applies from the next tick.
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval.send_replace(interval);
+    }
+
+    /// Cancels the sweeper, waits for the pass in progress to finish and
+    /// persists the cache a final time.
+    pub async fn shutdown(self) {
+        self.token.cancel();
+        if let Err(e) = self.supervisor.await {
+            error!("Cache maintenance supervisor failed: {}", e);
+        }
+    }
+}
+
+async fn run<T>(
+    cache: Arc<dyn Cache<T>>,
+    mut sweep_interval: watch::Receiver<Duration>,
+    persist_interval: Option<Duration>,
+    token: CancellationToken,
+) {
+    let mut next_sweep = Instant::now() + *sweep_interval.borrow_and_update();
+    let mut next_persist = persist_interval.map(|period| Instant::now() + period);
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            Ok(()) = sweep_interval.changed() => {
+                next_sweep = Instant::now() + *sweep_interval.borrow_and_update();
+            }
+            _ = time::sleep_until(next_sweep) => {
+                cache.invalidate_expired().await;
+                next_sweep = Instant::now() + *sweep_interval.borrow();
+            }
+            _ = time::sleep_until(next_persist.unwrap_or(next_sweep)), if next_persist.is_some() => {
+                persist(cache.as_ref()).await;
+                next_persist = persist_interval.map(|period| Instant::now() + period);
+            }
+        }
+    }
+    persist(cache.as_ref()).await;
+}
+
+async fn persist<T>(cache: &dyn Cache<T>) {
+    if let Err(e) = cache.persist().await {
+        error!("Failed to persist cache: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheResult;
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Default)]
+    struct CountingCache {
+        sweeps: AtomicUsize,
+        persists: AtomicUsize,
+        panic_on_sweep: bool,
+    }
+
+    #[async_trait]
+    impl Cache<String> for CountingCache {
+        async fn insert_item(&self, _key: String, _value: String, _ttl: u64) -> CacheResult<()> {
+            Ok(())
+        }
+
+        async fn retrieve_item(&self, _key: &str) -> CacheResult<Option<String>> {
+            Ok(None)
+        }
+
+        async fn remove_item(&self, _key: &str) -> CacheResult<()> {
+            Ok(())
+        }
+
+        async fn invalidate_expired(&self) {
+            if self.panic_on_sweep {
+                panic!("sweep failed");
+            }
+            self.sweeps.fetch_add(1, Ordering::SeqCst);
+        }
+
+        async fn persist(&self) -> CacheResult<()> {
+            self.persists.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn sweep_every(seconds: u64) -> MaintenanceConfig {
+        MaintenanceConfig {
+            sweep_interval: Duration::from_secs(seconds),
+            persist_interval: None,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sweeps_on_interval_until_shutdown() {
+        let cache = Arc::new(CountingCache::default());
+        let maintenance = Maintenance::start(
+            Arc::clone(&cache) as Arc<dyn Cache<String>>,
+            sweep_every(10),
+        );
+        let health = maintenance.health();
+
+        time::sleep(Duration::from_secs(35)).await;
+        assert_eq!(cache.sweeps.load(Ordering::SeqCst), 3);
+        assert_eq!(health.state(), SweeperState::Running);
+
+        maintenance.set_interval(Duration::from_secs(1));
+        time::sleep(Duration::from_millis(2500)).await;
+        assert_eq!(cache.sweeps.load(Ordering::SeqCst), 5);
+
+        maintenance.shutdown().await;
+        assert_eq!(health.state(), SweeperState::Stopped);
+        assert_eq!(cache.persists.load(Ordering::SeqCst), 1);
+        time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(cache.sweeps.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_persists_on_interval_and_shutdown() {
+        let cache = Arc::new(CountingCache::default());
+        let maintenance = Maintenance::start(
+            Arc::clone(&cache) as Arc<dyn Cache<String>>,
+            MaintenanceConfig {
+                sweep_interval: Duration::from_secs(1),
+                persist_interval: Some(Duration::from_secs(60)),
+            },
+        );
+
+        time::sleep(Duration::from_millis(130_500)).await;
+        assert_eq!(cache.persists.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.sweeps.load(Ordering::SeqCst), 130);
+
+        maintenance.shutdown().await;
+        assert_eq!(cache.persists.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_panicking_sweep_marks_health_failed() {
+        let cache = Arc::new(CountingCache {
+            panic_on_sweep: true,
+            ..Default::default()
+        });
+        let maintenance = Maintenance::start(cache as Arc<dyn Cache<String>>, sweep_every(1));
+        let health = maintenance.health();
+        assert!(health.is_healthy());
+
+        time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(health.state(), SweeperState::Failed);
+        assert!(!health.is_healthy());
+        maintenance.shutdown().await;
+    }
+}
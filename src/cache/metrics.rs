@@ -0,0 +1,53 @@
+use prometheus::{Counter, CounterVec, IntGauge, Opts, Registry};
+
+lazy_static::lazy_static! {
+    pub static ref EVICTION_COUNTER: Counter = Counter::with_opts(Opts::new("evictions", "Number of entries evicted to stay within capacity limits")).unwrap();
+    pub static ref HIT_COUNTER: CounterVec = CounterVec::new(Opts::new("hits", "Number of in-memory cache hits by eviction policy"), &["policy"]).unwrap();
+    pub static ref MISS_COUNTER: CounterVec = CounterVec::new(Opts::new("misses", "Number of in-memory cache misses by eviction policy"), &["policy"]).unwrap();
+    pub static ref TIER_HIT_COUNTER: CounterVec = CounterVec::new(Opts::new("tier_hits", "Number of tiered cache hits by tier"), &["tier"]).unwrap();
+    pub static ref TIER_MISS_COUNTER: CounterVec = CounterVec::new(Opts::new("tier_misses", "Number of tiered cache misses by tier"), &["tier"]).unwrap();
+    pub static ref INVALIDATION_COUNTER: Counter = Counter::with_opts(Opts::new("invalidations", "Number of keys evicted from the local tier by invalidation messages")).unwrap();
+    pub static ref COMPRESSION_INPUT_BYTES: CounterVec = CounterVec::new(Opts::new("compression_input_bytes", "Bytes of values stored compressed, before compression, by codec"), &["codec"]).unwrap();
+    pub static ref COMPRESSION_OUTPUT_BYTES: CounterVec = CounterVec::new(Opts::new("compression_output_bytes", "Bytes of values stored compressed, after compression, by codec"), &["codec"]).unwrap();
+    pub static ref COMPRESSION_SECONDS: CounterVec = CounterVec::new(Opts::new("compression_seconds", "Time spent compressing and decompressing values, by codec and operation"), &["codec", "operation"]).unwrap();
+    pub static ref ORIGIN_FETCH_COUNTER: CounterVec = CounterVec::new(Opts::new("origin_fetches", "Number of misses loaded from an origin, by namespace and outcome"), &["namespace", "outcome"]).unwrap();
+    pub static ref COALESCED_COUNTER: CounterVec = CounterVec::new(Opts::new("coalesced_requests", "Number of requests that joined a fetch already in flight for the same key, by flight"), &["flight"]).unwrap();
+    pub static ref FLIGHT_TIMEOUT_COUNTER: CounterVec = CounterVec::new(Opts::new("flight_timeouts", "Number of requests that stopped waiting for a fetch in flight, by flight"), &["flight"]).unwrap();
+    pub static ref SWEEPER_UP: IntGauge = IntGauge::with_opts(Opts::new("sweeper_up", "Whether the background expiry sweeper is running")).unwrap();
+}
+
+pub fn register(registry: &Registry) {
+    registry
+        .register(Box::new(EVICTION_COUNTER.clone()))
+        .unwrap();
+    registry.register(Box::new(HIT_COUNTER.clone())).unwrap();
+    registry.register(Box::new(MISS_COUNTER.clone())).unwrap();
+    registry
+        .register(Box::new(TIER_HIT_COUNTER.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(TIER_MISS_COUNTER.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(INVALIDATION_COUNTER.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(COMPRESSION_INPUT_BYTES.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(COMPRESSION_OUTPUT_BYTES.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(COMPRESSION_SECONDS.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(ORIGIN_FETCH_COUNTER.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(COALESCED_COUNTER.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(FLIGHT_TIMEOUT_COUNTER.clone()))
+        .unwrap();
+    registry.register(Box::new(SWEEPER_UP.clone())).unwrap();
+}
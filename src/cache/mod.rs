@@ -1,28 +1,204 @@
+pub mod compression;
+pub mod counter;
+pub mod disk_cache;
+pub mod error;
+pub mod eviction;
 pub mod in_memory_cache;
+pub mod invalidation;
+pub mod maintenance;
+pub mod metrics;
+pub mod origin;
+pub mod pattern;
 pub mod redis_cache;
 pub mod schema;
+pub mod single_flight;
+pub mod tiered_cache;
+pub mod value;
 
-pub use in_memory_cache::InMemoryCache;
-pub use redis_cache::RedisCache;
-pub use schema::Cache;
+pub use compression::{Codec, CompressedCache, CompressionConfig};
+pub use counter::CounterError;
+pub use disk_cache::DiskCache;
+pub use error::{CacheError, CacheResult};
+pub use eviction::{EvictionPolicy, EvictionPolicyKind};
+pub use in_memory_cache::{InMemoryCache, InMemoryConfig};
+pub use maintenance::{Maintenance, MaintenanceConfig, MaintenanceHealth};
+pub use origin::{Loaded, Origin, OriginError, OriginLoader};
+pub use pattern::KeyPattern;
+pub use redis_cache::{RedisCache, RedisTopology};
+pub use schema::{
+    Cache, EntryInfo, Expiry, KeyInfo, KeyPage, Precondition, MAX_TTL_SECS, RESERVED_KEY_PREFIX,
+};
+pub use single_flight::{FlightError, SingleFlight};
+pub use tiered_cache::TieredCache;
+pub use value::CacheValue;
 
-use bb8::Pool;
-use bb8_redis::RedisConnectionManager;
+use log::{info, warn};
 use std::env;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub async fn initialize_cache() -> Arc<dyn Cache<String>> {
+/// The backend chosen by `CACHE_BACKEND`, behind a `CompressedCache`.
+pub async fn initialize_cache() -> Arc<dyn Cache<CacheValue>> {
+    Arc::new(CompressedCache::new(
+        cache_backend().await,
+        compression_config(),
+    ))
+}
+
+async fn cache_backend() -> Arc<dyn Cache<CacheValue>> {
     let cache_backend = env::var("CACHE_BACKEND").unwrap_or_else(|_| "in_memory".to_string());
     match cache_backend.as_str() {
-        "redis" => {
-            let redis_url = env::var("REDIS_URL").expect("REDIS_URL must be set");
-            let manager = RedisConnectionManager::new(redis_url).expect("Invalid Redis URL");
-            let pool = Pool::builder()
-                .build(manager)
-                .await
-                .expect("Failed to create Redis pool");
-            Arc::new(RedisCache::new(pool))
+        "redis" => Arc::new(redis_cache().await),
+        "tiered" => {
+            let l1 = Arc::new(InMemoryCache::with_config(InMemoryConfig {
+                max_entries: env_limit("CACHE_L1_MAX_ENTRIES").or(Some(10_000)),
+                max_bytes: env_limit("CACHE_L1_MAX_BYTES"),
+                snapshot_path: None,
+                ..in_memory_config()
+            }));
+            invalidation::subscribe(redis_topology(), invalidation_channel(), &l1);
+            let l2: Arc<dyn Cache<CacheValue>> = Arc::new(redis_cache().await);
+            Arc::new(TieredCache::new(l1, l2, env_secs("CACHE_L1_TTL_SECS", 5)))
+        }
+        "disk" => {
+            let path = env::var("CACHE_DISK_PATH").unwrap_or_else(|_| "cache.log".to_string());
+            let sync_writes = env::var("CACHE_DISK_FSYNC").is_ok_and(|value| value == "true");
+            Arc::new(DiskCache::open(path, sync_writes).expect("Failed to open disk cache"))
+        }
+        _ => {
+            let config = in_memory_config();
+            let snapshot_path = config.snapshot_path.clone();
+            let cache = InMemoryCache::with_config(config);
+            if let Some(path) = snapshot_path.filter(|path| path.exists()) {
+                match cache.load_snapshot(&path).await {
+                    Ok(count) => info!("Restored {} entries from {}", count, path.display()),
+                    Err(e) => warn!("Ignoring snapshot {}: {}", path.display(), e),
+                }
+            }
+            Arc::new(cache)
         }
-        _ => Arc::new(InMemoryCache::new()),
     }
 }
+
+/// `CACHE_COMPRESSION` is `zstd`, `lz4` or `none` (the default);
+/// `CACHE_COMPRESSION_MIN_BYTES` is the smallest value worth compressing.
+fn compression_config() -> CompressionConfig {
+    let codec = env::var("CACHE_COMPRESSION")
+        .ok()
+        .filter(|codec| !codec.eq_ignore_ascii_case("none"))
+        .map(|codec| codec.parse().expect("Invalid CACHE_COMPRESSION"));
+    CompressionConfig {
+        codec,
+        min_bytes: env::var("CACHE_COMPRESSION_MIN_BYTES")
+            .map(|bytes| {
+                bytes
+                    .parse()
+                    .expect("CACHE_COMPRESSION_MIN_BYTES must be a non-negative integer")
+            })
+            .unwrap_or(CompressionConfig::default().min_bytes),
+    }
+}
+
+/// `REDIS_CLUSTER_NODES` selects a cluster and `REDIS_SENTINELS` a
+/// Sentinel-managed primary, both as comma-separated URLs; otherwise the
+/// single node at `REDIS_URL` is used.
+fn redis_topology() -> RedisTopology {
+    let urls = |list: String| list.split(',').map(|url| url.trim().to_string()).collect();
+    if let Ok(nodes) = env::var("REDIS_CLUSTER_NODES") {
+        RedisTopology::Cluster(urls(nodes))
+    } else if let Ok(sentinels) = env::var("REDIS_SENTINELS") {
+        RedisTopology::Sentinel {
+            sentinels: urls(sentinels),
+            service: env::var("REDIS_SENTINEL_SERVICE").unwrap_or_else(|_| "mymaster".to_string()),
+        }
+    } else {
+        RedisTopology::Standalone(env::var("REDIS_URL").expect("REDIS_URL must be set"))
+    }
+}
+
+fn invalidation_channel() -> String {
+    env::var("CACHE_INVALIDATION_CHANNEL").unwrap_or_else(|_| "cache:invalidations".to_string())
+}
+
+/// A `RedisCache` that publishes its writes for pods running the `tiered`
+/// backend, whichever backend this pod runs.
+async fn redis_cache() -> RedisCache {
+    RedisCache::connect(&redis_topology())
+        .await
+        .expect("Failed to connect to Redis")
+        .with_invalidation(invalidation_channel())
+}
+
+fn in_memory_config() -> InMemoryConfig {
+    InMemoryConfig {
+        max_entries: env_limit("CACHE_MAX_ENTRIES"),
+        max_bytes: env_limit("CACHE_MAX_BYTES"),
+        policy: env::var("CACHE_EVICTION_POLICY")
+            .map(|policy| policy.parse().expect("Invalid CACHE_EVICTION_POLICY"))
+            .unwrap_or_default(),
+        shards: env::var("CACHE_SHARDS")
+            .map(|shards| {
+                shards
+                    .parse()
+                    .expect("CACHE_SHARDS must be a positive integer")
+            })
+            .unwrap_or_else(|_| num_cpus::get() * 4),
+        snapshot_path: env::var("CACHE_SNAPSHOT_PATH").ok().map(PathBuf::from),
+    }
+}
+
+/// Starts background maintenance for `cache`: an expiry pass every
+/// `CACHE_SWEEP_INTERVAL_SECS` seconds (default 1) and `Cache::persist` every
+/// `CACHE_PERSIST_INTERVAL_SECS` seconds (default 60) and on shutdown.
+pub fn start_maintenance(cache: Arc<dyn Cache<CacheValue>>) -> Maintenance {
+    let sweep_interval = env_secs("CACHE_SWEEP_INTERVAL_SECS", 1);
+    let persist_interval = env_secs("CACHE_PERSIST_INTERVAL_SECS", 60);
+    Maintenance::start(
+        cache,
+        MaintenanceConfig {
+            sweep_interval: Duration::from_secs(sweep_interval.max(1)),
+            persist_interval: (persist_interval > 0).then(|| Duration::from_secs(persist_interval)),
+        },
+    )
+}
+
+fn env_secs(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .map(|secs| {
+            secs.parse()
+                .unwrap_or_else(|_| panic!("{} must be a non-negative integer", name))
+        })
+        .unwrap_or(default)
+}
+
+/// Wall-clock milliseconds since the Unix epoch, for deadlines that must
+/// survive a restart.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Fsyncs the directory holding `path`, making a rename into it durable.
+pub(crate) fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Reads an optional size limit; unset or `0` means unbounded.
+fn env_limit(name: &str) -> Option<usize> {
+    env::var(name)
+        .ok()
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a non-negative integer", name))
+        })
+        .filter(|&limit| limit > 0)
+}
@@ -0,0 +1,434 @@
+use super::error::CacheError;
+use super::metrics::ORIGIN_FETCH_COUNTER;
+use super::schema::{Cache, Precondition};
+use super::single_flight::{FlightError, SingleFlight};
+use super::value::CacheValue;
+use log::warn;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Largest origin response that is cached; anything bigger is a 502.
+const MAX_ORIGIN_BYTES: usize = 16 * 1024 * 1024;
+
+/// Where to load the keys of one namespace from on a miss.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Origin {
+    /// URL template. `{key}` is replaced by the whole key and `{id}` by the
+    /// part after the namespace, both percent-encoded.
+    pub url: String,
+    /// TTL in seconds of the loaded values.
+    pub ttl: u64,
+    /// How long to wait for the origin before answering 504.
+    #[serde(default = "Origin::default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How long a miss waits for the fetch in flight before answering 504;
+    /// `timeout_ms` if omitted. The fetch goes on, and caches its result,
+    /// after its waiters give up.
+    #[serde(default)]
+    pub max_wait_ms: Option<u64>,
+}
+
+impl Origin {
+    fn default_timeout_ms() -> u64 {
+        5000
+    }
+}
+
+/// Why a value could not be loaded from its origin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginError {
+    /// The origin did not answer within its timeout.
+    Timeout(String),
+    /// The origin could not be reached, failed, or sent an unusable response.
+    Upstream(String),
+}
+
+impl fmt::Display for OriginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OriginError::Timeout(e) => write!(f, "origin timed out: {}", e),
+            OriginError::Upstream(e) => write!(f, "origin failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for OriginError {}
+
+impl From<reqwest::Error> for OriginError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            OriginError::Timeout(e.to_string())
+        } else {
+            OriginError::Upstream(e.to_string())
+        }
+    }
+}
+
+/// A value loaded from an origin, with the version and TTL it was cached
+/// with. The version is 0 if caching it failed. If a client wrote the key
+/// while the origin was answering, this is the client's entry instead, whose
+/// TTL is `None` if it never expires.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loaded {
+    pub value: CacheValue,
+    pub version: u64,
+    pub ttl: Option<u64>,
+}
+
+/// Read-through loading: fetches missed keys from the origin configured for
+/// their namespace, the part of the key before the first `:`, and caches
+/// them.
+///
+/// Concurrent misses on the same key are coalesced into one fetch and one
+/// write, whose result they all receive. Each waits at most the origin's
+/// `max_wait_ms`.
+pub struct OriginLoader {
+    client: reqwest::Client,
+    origins: HashMap<String, Origin>,
+    flights: SingleFlight<Result<Option<Loaded>, OriginError>>,
+}
+
+impl OriginLoader {
+    pub fn new(origins: HashMap<String, Origin>) -> Self {
+        for (namespace, origin) in &origins {
+            assert!(
+                origin.ttl > 0,
+                "origin of namespace {:?} needs a ttl of at least 1 second",
+                namespace
+            );
+        }
+        Self {
+            client: reqwest::Client::new(),
+            origins,
+            flights: SingleFlight::new("origin"),
+        }
+    }
+
+    /// Reads `CACHE_ORIGINS`, a JSON object from namespace to origin, such as
+    /// `{"user": {"url": "http://users/api/users/{id}", "ttl": 300}}`. No
+    /// namespace has an origin if it is unset.
+    pub fn from_env() -> Self {
+        let origins = env::var("CACHE_ORIGINS")
+            .map(|origins| serde_json::from_str(&origins).expect("Invalid CACHE_ORIGINS"))
+            .unwrap_or_default();
+        Self::new(origins)
+    }
+
+    /// Fetches `key` from the origin of its namespace and writes it to
+    /// `cache`. `None` if the namespace has no origin or the origin answers
+    /// 404. A value that cannot be cached is still returned.
+    pub async fn load(
+        &self,
+        cache: &Arc<dyn Cache<CacheValue>>,
+        key: &str,
+    ) -> Result<Option<Loaded>, OriginError> {
+        let (namespace, _) = key.split_once(':').unwrap_or((key, ""));
+        let Some(origin) = self.origins.get(namespace) else {
+            return Ok(None);
+        };
+        let work = fetch_and_store(
+            self.client.clone(),
+            origin.clone(),
+            Arc::clone(cache),
+            key.to_string(),
+        );
+        let max_wait = Duration::from_millis(origin.max_wait_ms.unwrap_or(origin.timeout_ms));
+        match self.flights.run(key, max_wait, work).await {
+            Ok(result) => result,
+            Err(FlightError::TimedOut) => Err(OriginError::Timeout(format!(
+                "no result for {} within {:?}",
+                key, max_wait
+            ))),
+            Err(e) => Err(OriginError::Upstream(e.to_string())),
+        }
+    }
+}
+
+async fn fetch_and_store(
+    client: reqwest::Client,
+    origin: Origin,
+    cache: Arc<dyn Cache<CacheValue>>,
+    key: String,
+) -> Result<Option<Loaded>, OriginError> {
+    let (namespace, id) = key.split_once(':').unwrap_or((&key, ""));
+    let result = fetch(&client, &origin, &key, id).await;
+    let outcome = match &result {
+        Ok(Some(_)) => "loaded",
+        Ok(None) => "not_found",
+        Err(OriginError::Timeout(_)) => "timeout",
+        Err(OriginError::Upstream(_)) => "error",
+    };
+    ORIGIN_FETCH_COUNTER
+        .with_label_values(&[namespace, outcome])
+        .inc();
+    let Some(value) = result? else {
+        return Ok(None);
+    };
+    let value = value.stamped();
+    // A client write that lands while the origin answers is fresher, so the
+    // origin's value only fills a key that is still absent.
+    let store = |condition| {
+        cache.write_item(
+            key.clone(),
+            value.clone(),
+            Some(origin.ttl),
+            condition,
+            &[],
+            false,
+        )
+    };
+    let written = match store(Precondition::Absent).await {
+        Err(CacheError::Unsupported(_)) => store(Precondition::Always).await,
+        written => written,
+    };
+    let version = match written {
+        Ok(Some(version)) => version,
+        Ok(None) => match cache.retrieve_entry(&key).await {
+            Ok(Some(entry)) => {
+                return Ok(Some(Loaded {
+                    value: entry.value,
+                    version: entry.version,
+                    ttl: entry.ttl,
+                }))
+            }
+            Ok(None) => 0,
+            Err(e) => {
+                warn!(
+                    "Failed to read {} written during its origin load: {}",
+                    key, e
+                );
+                0
+            }
+        },
+        Err(e) => {
+            warn!("Not caching {} loaded from its origin: {}", key, e);
+            0
+        }
+    };
+    Ok(Some(Loaded {
+        value,
+        version,
+        ttl: Some(origin.ttl),
+    }))
+}
+
+async fn fetch(
+    client: &reqwest::Client,
+    origin: &Origin,
+    key: &str,
+    id: &str,
+) -> Result<Option<CacheValue>, OriginError> {
+    let url = origin
+        .url
+        .replace("{key}", &encode_component(key))
+        .replace("{id}", &encode_component(id));
+    let mut response = client
+        .get(&url)
+        .timeout(Duration::from_millis(origin.timeout_ms))
+        .send()
+        .await?;
+    match response.status() {
+        StatusCode::NOT_FOUND => return Ok(None),
+        status if !status.is_success() => {
+            return Err(OriginError::Upstream(format!(
+                "{} answered {}",
+                url, status
+            )))
+        }
+        _ => {}
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_ORIGIN_BYTES {
+            return Err(OriginError::Upstream(format!(
+                "{} sent more than {} bytes",
+                url, MAX_ORIGIN_BYTES
+            )));
+        }
+    }
+    Ok(Some(CacheValue::new(body, content_type)))
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters, so a key
+/// stays within one path segment or query value.
+fn encode_component(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::InMemoryCache;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serves `/items/{id}` and counts the requests: `missing` is a 404,
+    /// `broken` a 500 and `slow` takes a second; anything else is echoed back
+    /// as JSON after 50ms.
+    async fn stub_origin() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = web::Data::from(Arc::clone(&requests));
+        let server = HttpServer::new(move || {
+            App::new().app_data(counted.clone()).route(
+                "/items/{id}",
+                web::get().to(
+                    |id: web::Path<String>, requests: web::Data<AtomicUsize>| async move {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        match id.as_str() {
+                            "missing" => HttpResponse::NotFound().finish(),
+                            "broken" => HttpResponse::InternalServerError().finish(),
+                            "slow" => {
+                                tokio::time::sleep(Duration::from_secs(1)).await;
+                                HttpResponse::Ok().finish()
+                            }
+                            id => {
+                                tokio::time::sleep(Duration::from_millis(50)).await;
+                                HttpResponse::Ok()
+                                    .content_type("application/json")
+                                    .body(format!(r#"{{"id":"{}"}}"#, id))
+                            }
+                        }
+                    },
+                ),
+            )
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let address = server.addrs()[0];
+        tokio::spawn(server.run());
+        (format!("http://{}", address), requests)
+    }
+
+    fn loader(base: &str) -> OriginLoader {
+        OriginLoader::new(HashMap::from([(
+            "item".to_string(),
+            Origin {
+                url: format!("{}/items/{{id}}", base),
+                ttl: 30,
+                timeout_ms: 200,
+                max_wait_ms: None,
+            },
+        )]))
+    }
+
+    fn cache() -> Arc<dyn Cache<CacheValue>> {
+        Arc::new(InMemoryCache::new())
+    }
+
+    #[tokio::test]
+    async fn test_load_maps_origin_responses() {
+        let (base, _) = stub_origin().await;
+        let loader = loader(&base);
+        let cache = cache();
+
+        let loaded = loader.load(&cache, "item:a b").await.unwrap().unwrap();
+        assert_eq!(loaded.value.as_text(), Some(r#"{"id":"a b"}"#));
+        assert_eq!(
+            loaded.value.content_type.as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(loaded.ttl, Some(30));
+        assert_eq!(
+            cache.retrieve_versioned("item:a b").await.unwrap(),
+            Some((loaded.value, loaded.version))
+        );
+
+        assert_eq!(loader.load(&cache, "item:missing").await, Ok(None));
+        assert_eq!(loader.load(&cache, "other:a").await, Ok(None));
+        assert!(matches!(
+            loader.load(&cache, "item:broken").await,
+            Err(OriginError::Upstream(_))
+        ));
+        assert!(matches!(
+            loader.load(&cache, "item:slow").await,
+            Err(OriginError::Timeout(_))
+        ));
+        assert!(matches!(
+            self::loader("http://127.0.0.1:1")
+                .load(&cache, "item:a")
+                .await,
+            Err(OriginError::Upstream(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_fetch() {
+        let (base, requests) = stub_origin().await;
+        let loader = Arc::new(loader(&base));
+        let cache = cache();
+        let misses: Vec<_> = (0..20)
+            .map(|_| {
+                let (loader, cache) = (Arc::clone(&loader), Arc::clone(&cache));
+                tokio::spawn(async move { loader.load(&cache, "item:hot").await })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for miss in misses {
+            results.push(miss.await.unwrap().unwrap().unwrap());
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|loaded| *loaded == results[0]));
+    }
+
+    #[tokio::test]
+    async fn test_load_keeps_a_value_written_during_the_fetch() {
+        let (base, _) = stub_origin().await;
+        let loader = Arc::new(loader(&base));
+        let cache = cache();
+        let load = {
+            let (loader, cache) = (Arc::clone(&loader), Arc::clone(&cache));
+            tokio::spawn(async move { loader.load(&cache, "item:raced").await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let written = CacheValue::new(b"fresh".to_vec(), None);
+        let version = cache
+            .write_item(
+                "item:raced".to_string(),
+                written.clone(),
+                Some(60),
+                Precondition::Always,
+                &[],
+                false,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let loaded = load.await.unwrap().unwrap().unwrap();
+        assert_eq!(loaded.value, written);
+        assert_eq!(loaded.version, version);
+        assert_eq!(loaded.ttl, Some(60));
+        assert_eq!(
+            cache.retrieve_versioned("item:raced").await.unwrap(),
+            Some((written, version))
+        );
+    }
+
+    #[test]
+    fn test_keys_are_percent_encoded() {
+        assert_eq!(encode_component("a/b c?d=é"), "a%2Fb%20c%3Fd%3D%C3%A9");
+        assert_eq!(encode_component("Az09-._~"), "Az09-._~");
+    }
+}
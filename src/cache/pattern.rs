@@ -0,0 +1,184 @@
+/// Selects keys for bulk operations such as `Cache::remove_matching`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyPattern {
+    /// Every key that starts with this string.
+    Prefix(String),
+    /// Redis glob syntax: `*`, `?`, `[abc]`, `[^a-z]`, and `\` to escape.
+    Glob(String),
+}
+
+impl KeyPattern {
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            KeyPattern::Prefix(prefix) => key.starts_with(prefix.as_str()),
+            KeyPattern::Glob(glob) => {
+                let pattern: Vec<char> = glob.chars().collect();
+                let key: Vec<char> = key.chars().collect();
+                glob_match(&pattern, &key)
+            }
+        }
+    }
+
+    /// The literal text every matching key starts with, so ordered indexes
+    /// only need to visit keys with that prefix.
+    pub fn literal_prefix(&self) -> String {
+        match self {
+            KeyPattern::Prefix(prefix) => prefix.clone(),
+            KeyPattern::Glob(glob) => {
+                let mut prefix = String::new();
+                let mut chars = glob.chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '*' | '?' | '[' => break,
+                        '\\' => match chars.next() {
+                            Some(escaped) => prefix.push(escaped),
+                            None => break,
+                        },
+                        c => prefix.push(c),
+                    }
+                }
+                prefix
+            }
+        }
+    }
+
+    /// The pattern for `SCAN ... MATCH`.
+    pub fn redis_glob(&self) -> String {
+        match self {
+            KeyPattern::Prefix(prefix) => {
+                let mut glob = String::with_capacity(prefix.len() + 1);
+                for c in prefix.chars() {
+                    if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                        glob.push('\\');
+                    }
+                    glob.push(c);
+                }
+                glob.push('*');
+                glob
+            }
+            KeyPattern::Glob(glob) => glob.clone(),
+        }
+    }
+}
+
+/// Matches like Redis' `stringmatchlen`, backtracking only to the last `*`.
+fn glob_match(pattern: &[char], key: &[char]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    let mut star = None;
+    while k < key.len() {
+        if pattern.get(p) == Some(&'*') {
+            star = Some((p + 1, k));
+            p += 1;
+        } else if let Some(next) = match_one(pattern, p, key[k]) {
+            p = next;
+            k += 1;
+        } else if let Some((after_star, start)) = star {
+            p = after_star;
+            k = start + 1;
+            star = Some((after_star, start + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// If the token at `pattern[p]` matches `c`, the index of the next token.
+/// A `[` without its `]` is a literal `[`.
+fn match_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match *pattern.get(p)? {
+        '?' => Some(p + 1),
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        '[' => {
+            let mut i = p + 1;
+            let negate = pattern.get(i) == Some(&'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != ']' {
+                if pattern[i] == '\\' && i + 1 < pattern.len() {
+                    i += 1;
+                    matched |= pattern[i] == c;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+                    let (low, high) = (
+                        pattern[i].min(pattern[i + 2]),
+                        pattern[i].max(pattern[i + 2]),
+                    );
+                    matched |= (low..=high).contains(&c);
+                    i += 2;
+                } else {
+                    matched |= pattern[i] == c;
+                }
+                i += 1;
+            }
+            if i == pattern.len() {
+                return (c == '[').then_some(p + 1);
+            }
+            (matched != negate).then_some(i + 1)
+        }
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str) -> KeyPattern {
+        KeyPattern::Glob(pattern.to_string())
+    }
+
+    #[test]
+    fn test_glob_matching() {
+        assert!(glob("user:123:*").matches("user:123:profile"));
+        assert!(glob("user:123:*").matches("user:123:"));
+        assert!(!glob("user:123:*").matches("user:1234:profile"));
+        assert!(glob("*:v7:*").matches("catalog:v7:item:9"));
+        assert!(glob("h?llo").matches("héllo"));
+        assert!(glob("h[ae]llo").matches("hallo"));
+        assert!(!glob("h[^e]llo").matches("hello"));
+        assert!(glob("item:[0-9]").matches("item:5"));
+        assert!(!glob("item:[0-9]").matches("item:x"));
+        assert!(glob("a\\*b").matches("a*b"));
+        assert!(!glob("a\\*b").matches("axb"));
+        assert!(glob("*a*b*").matches("xxaxxbxx"));
+        assert!(!glob("*a*b").matches("xxbxxa"));
+    }
+
+    #[test]
+    fn test_unclosed_class_is_a_literal_bracket() {
+        assert!(glob("a[b").matches("a[b"));
+        assert!(!glob("a[b").matches("ab"));
+        assert!(!glob("a[b").matches("a"));
+        assert!(glob("[").matches("["));
+        assert!(!glob("[").matches("x"));
+        assert!(!glob("[").matches(""));
+        assert!(glob("[^*").matches("[^x"));
+        assert!(glob("x[\\").matches("x[\\"));
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(glob("user:123:*").literal_prefix(), "user:123:");
+        assert_eq!(glob("a\\*b?c").literal_prefix(), "a*b");
+        assert_eq!(glob("*:v7").literal_prefix(), "");
+        assert_eq!(
+            KeyPattern::Prefix("user:".to_string()).literal_prefix(),
+            "user:"
+        );
+    }
+
+    #[test]
+    fn test_redis_glob_escapes_prefixes() {
+        assert_eq!(
+            KeyPattern::Prefix("user:".to_string()).redis_glob(),
+            "user:*"
+        );
+        assert_eq!(
+            KeyPattern::Prefix("a*b?[c]\\".to_string()).redis_glob(),
+            "a\\*b\\?\\[c\\]\\\\*"
+        );
+        assert_eq!(glob("user:*").redis_glob(), "user:*");
+    }
+}
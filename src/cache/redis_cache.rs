@@ -1,19 +1,807 @@
-use super::schema::Cache;
+use super::counter::{self, CounterError};
+use super::error::{CacheError, CacheResult};
+use super::invalidation;
+use super::now_millis;
+use super::pattern::KeyPattern;
+use super::schema::{
+    Cache, EntryInfo, Expiry, KeyInfo, KeyPage, Precondition, MAX_TTL_SECS, RESERVED_KEY_PREFIX,
+};
 use async_trait::async_trait;
-use bb8::Pool;
+use bb8::{ManageConnection, Pool, PooledConnection, RunError};
 use bb8_redis::RedisConnectionManager;
-use log::info;
-use redis::AsyncCommands;
-use std::io;
-use tokio::time::Duration;
+use futures_util::future::join_all;
+use log::debug;
+use redis::aio::{ConnectionLike, MultiplexedConnection, PubSub};
+use redis::cluster::ClusterClient;
+use redis::cluster_async::ClusterConnection;
+use redis::cluster_routing::{Route, RoutingInfo, SingleNodeRoutingInfo, SlotAddr};
+use redis::sentinel::Sentinel;
+use redis::{
+    Cmd, ErrorKind, FromRedisValue, Pipeline, RedisError, RedisFuture, RedisResult, Script, Value,
+};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+
+/// How the service reaches its Redis deployment.
+#[derive(Clone, Debug)]
+pub enum RedisTopology {
+    /// A single node at this URL.
+    Standalone(String),
+    /// Seed nodes of a Redis Cluster; the other nodes are discovered from them.
+    Cluster(Vec<String>),
+    /// Sentinels monitoring the primary named `service`.
+    Sentinel {
+        sentinels: Vec<String>,
+        service: String,
+    },
+}
+
+impl RedisTopology {
+    /// Opens a pub/sub connection that receives every published message.
+    pub async fn pubsub(&self) -> RedisResult<PubSub> {
+        match self {
+            RedisTopology::Standalone(url) => {
+                redis::Client::open(url.as_str())?.get_async_pubsub().await
+            }
+            // Cluster-wide PUBLISH reaches every node, so any reachable seed will do.
+            RedisTopology::Cluster(nodes) => {
+                let mut last_error =
+                    RedisError::from((ErrorKind::InvalidClientConfig, "no cluster nodes"));
+                for node in nodes {
+                    match redis::Client::open(node.as_str())?.get_async_pubsub().await {
+                        Ok(pubsub) => return Ok(pubsub),
+                        Err(e) => last_error = e,
+                    }
+                }
+                Err(last_error)
+            }
+            RedisTopology::Sentinel { sentinels, service } => {
+                Sentinel::build(sentinels.clone())?
+                    .async_master_for(service, None)
+                    .await?
+                    .get_async_pubsub()
+                    .await
+            }
+        }
+    }
+}
+
+/// bb8 manager for the primary that the sentinels currently report.
+///
+/// Connections are checked with `ROLE` on checkout, so once a failover
+/// demotes the old primary its connections are dropped and replaced by ones
+/// to the new primary.
+pub struct SentinelConnectionManager {
+    sentinel: Mutex<Sentinel>,
+    service: String,
+}
+
+impl SentinelConnectionManager {
+    pub fn new(sentinels: Vec<String>, service: String) -> RedisResult<Self> {
+        Ok(Self {
+            sentinel: Mutex::new(Sentinel::build(sentinels)?),
+            service,
+        })
+    }
+}
+
+#[async_trait]
+impl ManageConnection for SentinelConnectionManager {
+    type Connection = MultiplexedConnection;
+    type Error = RedisError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        let client = self
+            .sentinel
+            .lock()
+            .await
+            .async_master_for(&self.service, None)
+            .await?;
+        client.get_multiplexed_async_connection().await
+    }
+
+    async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        let role: Vec<Value> = redis::cmd("ROLE").query_async(conn).await?;
+        match role.first().map(String::from_redis_value).transpose()? {
+            Some(role) if role == "master" => Ok(()),
+            _ => Err(RedisError::from((
+                ErrorKind::ReadOnly,
+                "connection no longer points at the primary",
+            ))),
+        }
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+enum Connections {
+    Pool(Pool<RedisConnectionManager>),
+    Sentinel(Pool<SentinelConnectionManager>),
+    /// Multiplexed and slot-aware; follows `MOVED` and `ASK` redirections and
+    /// refreshes its slot map on its own.
+    Cluster(ClusterConnection),
+}
+
+/// A connection taken from `Connections` for one request.
+enum Connection<'a> {
+    Pooled(PooledConnection<'a, RedisConnectionManager>),
+    Sentinel(PooledConnection<'a, SentinelConnectionManager>),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for Connection<'_> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Connection::Pooled(conn) => conn.req_packed_command(cmd),
+            Connection::Sentinel(conn) => conn.req_packed_command(cmd),
+            Connection::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipe: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Connection::Pooled(conn) => conn.req_packed_commands(pipe, offset, count),
+            Connection::Sentinel(conn) => conn.req_packed_commands(pipe, offset, count),
+            Connection::Cluster(conn) => conn.req_packed_commands(pipe, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Connection::Pooled(conn) => conn.get_db(),
+            Connection::Sentinel(conn) => conn.get_db(),
+            Connection::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+/// Conditional write. Values are stored as `<version>:<json>`, or as
+/// `<version>,<tag>,<tag>\n<json>` when tagged; a sliding value has
+/// `~<ttl>` right after its version. Values written before versioning have
+/// no prefix and count as version 0. The new version is at least the server
+/// time in microseconds, so it keeps growing even if the key is deleted and
+/// written again.
+///
+/// ARGV: json, ttl (`""` for none), condition (`""`, `"nx"`, `"xx"` or a version),
+/// invalidation channel (`""` for none), the text between version and json
+/// (`":"`, `"~60:"`, or `",a,b\n"` for tags `a` and `b`), sender of the
+/// invalidation.
+/// Returns the new version, or 0 if the condition failed.
+const WRITE_SCRIPT_SOURCE: &str = r"
+redis.replicate_commands()
+local current = redis.call('GET', KEYS[1])
+local version = 0
+if current then
+    version = tonumber(string.match(current, '^(%d+)[~,:]') or '0')
+end
+local condition = ARGV[3]
+if (condition == 'nx' and current)
+    or (condition == 'xx' and not current)
+    or (condition ~= '' and condition ~= 'nx' and condition ~= 'xx'
+        and (not current or version ~= tonumber(condition))) then
+    return 0
+end
+local now = redis.call('TIME')
+local next = math.max(version + 1, tonumber(now[1]) * 1000000 + tonumber(now[2]))
+local stored = string.format('%d', next) .. ARGV[5] .. ARGV[1]
+if ARGV[2] ~= '' then
+    redis.call('SET', KEYS[1], stored, 'EX', ARGV[2])
+else
+    redis.call('SET', KEYS[1], stored)
+end
+if ARGV[4] ~= '' then
+    redis.call('PUBLISH', ARGV[4], ARGV[6] .. ' ' .. KEYS[1])
+end
+return next
+";
+
+/// Atomic `INCRBY` that understands the `<version>:<json>` envelope, so a
+/// counter is an ordinary value that can also be read, written and deleted.
+/// The JSON is decoded with `cjson`: a number, a string of one, or an object
+/// whose `text` is one, as the HTTP API stores values with metadata. An
+/// object keeps its other fields; its `written_at` is renewed and its `hash`
+/// dropped, as it no longer matches. Lua numbers are doubles, so counters are
+/// limited to ±(2^53 - 1).
+///
+/// ARGV: delta, `"1"` if the JSON should be a string rather than a number,
+/// TTL for a new key (`""` for none), invalidation channel (`""` for
+/// none), sender of the invalidation.
+/// Returns the new value, or a `NOTINT` or `OVERFLOW` error.
+const INCREMENT_SCRIPT_SOURCE: &str = r#"
+redis.replicate_commands()
+local current = redis.call('GET', KEYS[1])
+local version, value, head, object = 0, 0, ':', nil
+if current then
+    local prefix, meta, json = string.match(current, '^(%d+)(~?%d*,[^\n]*\n)(.*)$')
+    if not prefix then
+        prefix, meta, json = string.match(current, '^(%d+)(~?%d*:)(.*)$')
+    end
+    if prefix then
+        version, head = tonumber(prefix), meta
+    else
+        json = current
+    end
+    local ok, decoded = pcall(cjson.decode, json)
+    local digits
+    if ok and type(decoded) == 'number' then
+        digits = string.match(json, '^-?%d+$')
+    elseif ok and type(decoded) == 'string' then
+        digits = string.match(decoded, '^-?%d+$')
+    elseif ok and type(decoded) == 'table' and type(decoded.text) == 'string' then
+        digits, object = string.match(decoded.text, '^-?%d+$'), decoded
+    end
+    if not digits then
+        return redis.error_reply('NOTINT value is not an integer')
+    end
+    value = tonumber(digits)
+end
+local result = value + tonumber(ARGV[1])
+if math.abs(value) > 9007199254740991 or math.abs(result) > 9007199254740991 then
+    return redis.error_reply('OVERFLOW increment would overflow')
+end
+local now = redis.call('TIME')
+local digits = string.format('%d', result)
+local json
+if object then
+    object.text = digits
+    object.written_at = tonumber(now[1]) * 1000 + math.floor(tonumber(now[2]) / 1000)
+    object.hash = nil
+    json = cjson.encode(object)
+elseif ARGV[2] == '1' then
+    json = '"' .. digits .. '"'
+else
+    json = digits
+end
+local next = math.max(version + 1, tonumber(now[1]) * 1000000 + tonumber(now[2]))
+local stored = string.format('%d', next) .. head .. json
+if current then
+    redis.call('SET', KEYS[1], stored, 'KEEPTTL')
+elseif ARGV[3] ~= '' then
+    redis.call('SET', KEYS[1], stored, 'EX', ARGV[3])
+else
+    redis.call('SET', KEYS[1], stored)
+end
+if ARGV[4] ~= '' then
+    redis.call('PUBLISH', ARGV[4], ARGV[5] .. ' ' .. KEYS[1])
+end
+return result
+"#;
+
+/// Adds ARGV[1] to the tag set KEYS[1] and makes the set live at least
+/// ARGV[2] seconds, or forever if ARGV[2] is `""`, so it expires with its
+/// longest-lived entry.
+const TAG_ADD_SCRIPT_SOURCE: &str = r"
+local ttl = redis.call('TTL', KEYS[1])
+redis.call('SADD', KEYS[1], ARGV[1])
+if ARGV[2] == '' then
+    redis.call('PERSIST', KEYS[1])
+elseif ttl == -2 or (ttl >= 0 and ttl < tonumber(ARGV[2])) then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+return 1
+";
+
+/// Changes the expiry of KEYS[1]: ARGV[1] is `in` or `at` with seconds in
+/// ARGV[2], or `never`. A sliding value stops sliding. Publishes the key on
+/// ARGV[3] unless it is `""`, as sent by ARGV[4], since a shorter TTL must
+/// reach other pods' copies too. Returns the value's tag list (`""` if untagged), or nil if
+/// the key does not exist.
+const EXPIRE_SCRIPT_SOURCE: &str = r"
+local current = redis.call('GET', KEYS[1])
+if not current then
+    return false
+end
+local version, rest = string.match(current, '^(%d+)~%d+(.*)$')
+if version then
+    redis.call('SET', KEYS[1], version .. rest, 'KEEPTTL')
+end
+if ARGV[1] == 'never' then
+    redis.call('PERSIST', KEYS[1])
+elseif ARGV[1] == 'at' then
+    redis.call('EXPIREAT', KEYS[1], ARGV[2])
+else
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+if ARGV[3] ~= '' then
+    redis.call('PUBLISH', ARGV[3], ARGV[4] .. ' ' .. KEYS[1])
+end
+return string.match(current, '^%d+~?%d*,([^\n]*)\n') or ''
+";
+
+/// Deletes KEYS[1] if its envelope still carries tag ARGV[1], publishing
+/// the invalidation on ARGV[2] unless it is `""`. The invalidation has no
+/// sender, so it reaches this pod as well: its L1 may hold copies of the
+/// key read from L2 without their tags. Returns 1 if it deleted.
+const TAG_REMOVE_SCRIPT_SOURCE: &str = r"
+local current = redis.call('GET', KEYS[1])
+if not current then
+    return 0
+end
+local tags = string.match(current, '^%d+~?%d*(,[^\n]*)\n')
+if not tags or not string.find(tags .. ',', ',' .. ARGV[1] .. ',', 1, true) then
+    return 0
+end
+redis.call('DEL', KEYS[1])
+if ARGV[2] ~= '' then
+    redis.call('PUBLISH', ARGV[2], ' ' .. KEYS[1])
+end
+return 1
+";
+
+/// Tag sets live next to the entries, under this prefix. Listing and bulk
+/// removal skip them.
+const TAG_SET_PREFIX: &str = RESERVED_KEY_PREFIX;
+
+/// Keys requested per SCAN round of a bulk removal.
+const REMOVE_SCAN_COUNT: usize = 500;
+
+lazy_static::lazy_static! {
+    static ref WRITE_SCRIPT: Script = Script::new(WRITE_SCRIPT_SOURCE);
+    static ref INCREMENT_SCRIPT: Script = Script::new(INCREMENT_SCRIPT_SOURCE);
+    static ref TAG_ADD_SCRIPT: Script = Script::new(TAG_ADD_SCRIPT_SOURCE);
+    static ref TAG_REMOVE_SCRIPT: Script = Script::new(TAG_REMOVE_SCRIPT_SOURCE);
+    static ref EXPIRE_SCRIPT: Script = Script::new(EXPIRE_SCRIPT_SOURCE);
+}
+
+fn without_tag_sets(mut keys: Vec<String>) -> Vec<String> {
+    keys.retain(|key| !key.starts_with(TAG_SET_PREFIX));
+    keys
+}
+
+fn invalid_cursor(cursor: &str) -> CacheError {
+    CacheError::InvalidInput(format!("invalid cursor: {}", cursor))
+}
+
+/// Connection failures, timeouts and a cluster or primary that is not ready
+/// make Redis unavailable; anything else Redis replies with is a backend error.
+impl From<RedisError> for CacheError {
+    fn from(e: RedisError) -> Self {
+        let unavailable = e.is_io_error()
+            || e.is_timeout()
+            || e.is_connection_dropped()
+            || e.is_connection_refusal()
+            || matches!(
+                e.kind(),
+                ErrorKind::ClusterDown
+                    | ErrorKind::MasterDown
+                    | ErrorKind::TryAgain
+                    | ErrorKind::BusyLoadingError
+                    | ErrorKind::ReadOnly
+            );
+        if unavailable {
+            CacheError::Unavailable(e.to_string())
+        } else {
+            CacheError::Backend(e.to_string())
+        }
+    }
+}
+
+impl From<RunError<RedisError>> for CacheError {
+    fn from(e: RunError<RedisError>) -> Self {
+        match e {
+            RunError::User(e) => e.into(),
+            RunError::TimedOut => {
+                CacheError::Unavailable("timed out waiting for a Redis connection".to_string())
+            }
+        }
+    }
+}
+
+/// The `~<ttl>` a sliding value is stored with, if it is sliding.
+fn sliding_window(stored: &str) -> Option<u64> {
+    let is_digit = |c: char| c.is_ascii_digit();
+    let version = stored.trim_start_matches(is_digit);
+    if version.len() == stored.len() {
+        return None;
+    }
+    let window = version.strip_prefix('~')?;
+    let digits = window.len() - window.trim_start_matches(is_digit).len();
+    window[..digits].parse().ok()
+}
+
+/// Splits a stored `<version>:<json>` or `<version>,<tags>\n<json>` value,
+/// skipping the `~<ttl>` of sliding ones; unversioned values get version 0.
+fn decode<T: DeserializeOwned>(stored: &str) -> CacheResult<(T, u64)> {
+    let is_digit = |c: char| c.is_ascii_digit();
+    let digits = stored.len() - stored.trim_start_matches(is_digit).len();
+    let (version, rest) = stored.split_at(digits);
+    let rest = match rest.strip_prefix('~') {
+        Some(window) => window.trim_start_matches(is_digit),
+        None => rest,
+    };
+    let json = match rest.strip_prefix(':') {
+        Some(json) => Some(json),
+        None if rest.starts_with(',') => rest.split_once('\n').map(|(_, json)| json),
+        None => None,
+    };
+    let (version, json) = match json.zip(version.parse().ok()) {
+        Some((json, version)) => (version, json),
+        None => (0, stored),
+    };
+    Ok((serde_json::from_str(json)?, version))
+}
 
 pub struct RedisCache {
-    pool: Pool<RedisConnectionManager>,
+    connections: Connections,
+    invalidation_channel: Option<String>,
 }
 
 impl RedisCache {
     pub fn new(pool: Pool<RedisConnectionManager>) -> Self {
-        Self { pool }
+        Self {
+            connections: Connections::Pool(pool),
+            invalidation_channel: None,
+        }
+    }
+
+    /// Connects to `topology`, pooling connections unless it is a cluster.
+    pub async fn connect(topology: &RedisTopology) -> RedisResult<Self> {
+        let connections = match topology {
+            RedisTopology::Standalone(url) => {
+                let manager = RedisConnectionManager::new(url.as_str())?;
+                Connections::Pool(Pool::builder().build(manager).await?)
+            }
+            RedisTopology::Cluster(nodes) => Connections::Cluster(
+                ClusterClient::new(nodes.clone())?
+                    .get_async_connection()
+                    .await?,
+            ),
+            RedisTopology::Sentinel { sentinels, service } => {
+                let manager = SentinelConnectionManager::new(sentinels.clone(), service.clone())?;
+                Connections::Sentinel(Pool::builder().build(manager).await?)
+            }
+        };
+        Ok(Self {
+            connections,
+            invalidation_channel: None,
+        })
+    }
+
+    /// Publishes every written or removed key on `channel` so other pods can
+    /// drop their local copies. The message shares the write's transaction,
+    /// except on a cluster, where it follows the write.
+    pub fn with_invalidation(mut self, channel: impl Into<String>) -> Self {
+        self.invalidation_channel = Some(channel.into());
+        self
+    }
+
+    async fn connection(&self) -> CacheResult<Connection<'_>> {
+        Ok(match &self.connections {
+            Connections::Pool(pool) => Connection::Pooled(pool.get().await?),
+            Connections::Sentinel(pool) => Connection::Sentinel(pool.get().await?),
+            Connections::Cluster(conn) => Connection::Cluster(conn.clone()),
+        })
+    }
+
+    async fn query<R: FromRedisValue>(&self, pipe: &Pipeline) -> CacheResult<R> {
+        pipe.query_async(&mut self.connection().await?)
+            .await
+            .map_err(CacheError::from)
+    }
+
+    /// The `WRITE_SCRIPT` arguments after the JSON value.
+    fn script_args(
+        &self,
+        ttl: Option<u64>,
+        condition: Precondition,
+        tags: &[String],
+        sliding: bool,
+    ) -> (String, String, &str, String) {
+        let ttl = ttl.map(|ttl| ttl.min(MAX_TTL_SECS));
+        let condition = match condition {
+            Precondition::Always => String::new(),
+            Precondition::Absent => "nx".to_string(),
+            Precondition::Present => "xx".to_string(),
+            Precondition::Version(version) => version.to_string(),
+        };
+        let channel = self.invalidation_channel.as_deref().unwrap_or("");
+        let mut head = match ttl {
+            Some(ttl) if sliding => format!("~{}", ttl),
+            _ => String::new(),
+        };
+        if tags.is_empty() {
+            head.push(':');
+        } else {
+            for tag in tags {
+                head.push(',');
+                head.push_str(tag);
+            }
+            head.push('\n');
+        }
+        let ttl = ttl.map_or_else(String::new, |ttl| ttl.to_string());
+        (ttl, condition, channel, head)
+    }
+
+    /// Runs `script`, whose source is `source`, once per `(key, args)`: in one
+    /// pipeline on a single node, or as concurrent calls on a cluster.
+    async fn eval_each<R: FromRedisValue>(
+        &self,
+        script: &Script,
+        source: &str,
+        calls: Vec<(String, Vec<String>)>,
+    ) -> CacheResult<Vec<R>> {
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+        if let Connections::Cluster(conn) = &self.connections {
+            return join_all(calls.into_iter().map(|(key, args)| {
+                let mut invocation = script.key(key);
+                for arg in args {
+                    invocation.arg(arg);
+                }
+                let mut conn = conn.clone();
+                async move { invocation.invoke_async(&mut conn).await }
+            }))
+            .await
+            .into_iter()
+            .collect::<RedisResult<Vec<R>>>()
+            .map_err(CacheError::from);
+        }
+        let mut pipe = redis::pipe();
+        // Loading first lets every EVALSHA below find the script.
+        pipe.cmd("SCRIPT").arg("LOAD").arg(source).ignore();
+        for (key, args) in calls {
+            pipe.cmd("EVALSHA")
+                .arg(script.get_hash())
+                .arg(1)
+                .arg(key)
+                .arg(args);
+        }
+        self.query(&pipe).await
+    }
+
+    /// Adds `key` to the set of each of `tags`, which then lives at least
+    /// `ttl` seconds, or forever if `ttl` is `None`.
+    async fn add_to_tags(&self, key: &str, ttl: Option<u64>, tags: &[String]) -> CacheResult<()> {
+        let ttl = ttl.map_or_else(String::new, |ttl| ttl.min(MAX_TTL_SECS).to_string());
+        let calls = tags
+            .iter()
+            .map(|tag| {
+                let args = vec![key.to_string(), ttl.clone()];
+                (format!("{}{}", TAG_SET_PREFIX, tag), args)
+            })
+            .collect();
+        self.eval_each::<i64>(&TAG_ADD_SCRIPT, TAG_ADD_SCRIPT_SOURCE, calls)
+            .await
+            .map(|_| ())
+    }
+
+    /// Runs the write in `pipe` and publishes the invalidation for each of
+    /// `keys`, if enabled. Returns the replies `pipe` does not ignore.
+    async fn write<R: FromRedisValue>(&self, mut pipe: Pipeline, keys: &[&str]) -> CacheResult<R> {
+        let Some(channel) = &self.invalidation_channel else {
+            return self.query(&pipe).await;
+        };
+        if let Connections::Cluster(_) = self.connections {
+            // PUBLISH is routed by its channel's slot, which need not be the keys'.
+            let reply = self.query(&pipe).await?;
+            let mut publish = redis::pipe();
+            for key in keys {
+                publish
+                    .publish(channel, invalidation::message(invalidation::pod_id(), key))
+                    .ignore();
+            }
+            self.query::<()>(&publish).await?;
+            return Ok(reply);
+        }
+        pipe.atomic();
+        for key in keys {
+            pipe.publish(channel, invalidation::message(invalidation::pod_id(), key))
+                .ignore();
+        }
+        self.query(&pipe).await
+    }
+
+    /// One SCAN round, returning the matching keys and the cursor for the
+    /// next round, if any.
+    async fn scan_round(
+        &self,
+        cursor: Option<&str>,
+        glob: &str,
+        count: usize,
+    ) -> CacheResult<(Vec<String>, Option<String>)> {
+        if let Connections::Cluster(conn) = &self.connections {
+            return Self::scan_cluster(conn, cursor, glob, count).await;
+        }
+        let cursor: u64 = match cursor {
+            Some(cursor) => cursor.parse().map_err(|_| invalid_cursor(cursor))?,
+            None => 0,
+        };
+        let mut pipe = redis::pipe();
+        pipe.cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(glob)
+            .arg("COUNT")
+            .arg(count);
+        let ((next, keys),): ((u64, Vec<String>),) = self.query(&pipe).await?;
+        Ok((
+            without_tag_sets(keys),
+            (next != 0).then(|| next.to_string()),
+        ))
+    }
+
+    /// Scans the primaries one after another. The cursor is
+    /// `<slot>:<scan cursor>`, where `<slot>` is the lowest slot of the
+    /// primary being scanned. Keys in slots that move during the scan may be
+    /// missed or listed twice.
+    async fn scan_cluster(
+        conn: &ClusterConnection,
+        cursor: Option<&str>,
+        glob: &str,
+        count: usize,
+    ) -> CacheResult<(Vec<String>, Option<String>)> {
+        let primaries = Self::primary_slots(conn).await?;
+        let (slot, node_cursor) = match cursor {
+            Some(cursor) => cursor
+                .split_once(':')
+                .and_then(|(slot, node_cursor)| {
+                    Some((slot.parse().ok()?, node_cursor.parse().ok()?))
+                })
+                .ok_or_else(|| invalid_cursor(cursor))?,
+            None => match primaries.first() {
+                Some(&slot) => (slot, 0u64),
+                None => return Ok((Vec::new(), None)),
+            },
+        };
+        let routing = RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(Route::new(
+            slot,
+            SlotAddr::Master,
+        )));
+        let mut scan = redis::cmd("SCAN");
+        scan.arg(node_cursor)
+            .arg("MATCH")
+            .arg(glob)
+            .arg("COUNT")
+            .arg(count);
+        let reply = conn
+            .clone()
+            .route_command(&scan, routing)
+            .await
+            .map_err(CacheError::from)?;
+        let (next, keys): (u64, Vec<String>) =
+            redis::from_redis_value(&reply).map_err(CacheError::from)?;
+        let cursor = if next != 0 {
+            Some(format!("{}:{}", slot, next))
+        } else {
+            primaries
+                .into_iter()
+                .find(|&primary| primary > slot)
+                .map(|primary| format!("{}:0", primary))
+        };
+        Ok((without_tag_sets(keys), cursor))
+    }
+
+    /// PTTL of each key, in milliseconds, or -1 / -2 as Redis reports them.
+    async fn ttls(&self, keys: &[String]) -> CacheResult<Vec<i64>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        if let Connections::Cluster(conn) = &self.connections {
+            return join_all(keys.iter().map(|key| {
+                let mut conn = conn.clone();
+                async move { redis::cmd("PTTL").arg(key).query_async(&mut conn).await }
+            }))
+            .await
+            .into_iter()
+            .collect::<RedisResult<Vec<i64>>>()
+            .map_err(CacheError::from);
+        }
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.pttl(key);
+        }
+        self.query(&pipe).await
+    }
+
+    /// The lowest slot served by each primary, in ascending order.
+    async fn primary_slots(conn: &ClusterConnection) -> CacheResult<Vec<u16>> {
+        let ranges: Vec<Vec<Value>> = redis::cmd("CLUSTER")
+            .arg("SLOTS")
+            .query_async(&mut conn.clone())
+            .await
+            .map_err(CacheError::from)?;
+        let mut lowest = BTreeMap::new();
+        for range in &ranges {
+            let (Some(start), Some(primary)) = (range.first(), range.get(2)) else {
+                continue;
+            };
+            let start: u16 = redis::from_redis_value(start).map_err(CacheError::from)?;
+            let primary: Vec<Value> = redis::from_redis_value(primary).map_err(CacheError::from)?;
+            let address: (String, u16) =
+                redis::from_redis_value(&Value::Array(primary.into_iter().take(2).collect()))
+                    .map_err(CacheError::from)?;
+            let slot = lowest.entry(address).or_insert(start);
+            *slot = (*slot).min(start);
+        }
+        let mut slots: Vec<u16> = lowest.into_values().collect();
+        slots.sort_unstable();
+        Ok(slots)
+    }
+
+    /// Restarts the TTL of each sliding `(key, window)` with `GETEX`, returning
+    /// the values it slid; `None` for keys removed since they were read.
+    async fn slide(&self, keys: &[(&str, u64)]) -> CacheResult<Vec<Option<String>>> {
+        if let Connections::Cluster(conn) = &self.connections {
+            return join_all(keys.iter().map(|&(key, window)| {
+                let mut conn = conn.clone();
+                async move {
+                    redis::cmd("GETEX")
+                        .arg(key)
+                        .arg("EX")
+                        .arg(window)
+                        .query_async(&mut conn)
+                        .await
+                }
+            }))
+            .await
+            .into_iter()
+            .collect::<RedisResult<Vec<Option<String>>>>()
+            .map_err(CacheError::from);
+        }
+        let mut pipe = redis::pipe();
+        for &(key, window) in keys {
+            pipe.cmd("GETEX").arg(key).arg("EX").arg(window);
+        }
+        self.query(&pipe).await
+    }
+
+    /// Deletes `keys` and returns how many existed. On a cluster each key is
+    /// deleted on its own, since they may live in different slots.
+    async fn delete(&self, keys: &[String]) -> CacheResult<u64> {
+        if self.is_cluster() {
+            let mut deleted = 0;
+            for count in join_all(keys.iter().map(|key| {
+                let mut pipe = redis::pipe();
+                pipe.del(key);
+                async move { self.write::<(u64,)>(pipe, &[key]).await }
+            }))
+            .await
+            {
+                deleted += count?.0;
+            }
+            return Ok(deleted);
+        }
+        let mut pipe = redis::pipe();
+        pipe.del(keys);
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        Ok(self.write::<(u64,)>(pipe, &keys).await?.0)
+    }
+
+    /// A cluster spreads a batch's keys over slots that no single pipeline may
+    /// span, so batches there run as concurrent per-key requests over the
+    /// multiplexed connection.
+    fn is_cluster(&self) -> bool {
+        matches!(self.connections, Connections::Cluster(_))
+    }
+}
+
+/// Pairs SCAN results with their PTTL replies, dropping keys that expired in
+/// between (-2) and mapping keys without expiry (-1) to `None`.
+fn key_infos(keys: Vec<String>, ttls: Vec<i64>) -> Vec<KeyInfo> {
+    keys.into_iter()
+        .zip(ttls)
+        .filter(|&(_, ttl)| ttl != -2)
+        .map(|(key, ttl)| KeyInfo {
+            key,
+            ttl: u64::try_from(ttl).ok().map(|ms| ms.div_ceil(1000)),
+        })
+        .collect()
+}
+
+/// Copies `e` into every result that is still `Ok`, for a pipeline that
+/// failed as a whole.
+fn fail_all(results: &mut [CacheResult<()>], e: &CacheError) {
+    for result in results.iter_mut().filter(|result| result.is_ok()) {
+        *result = Err(e.clone());
     }
 }
 
@@ -22,43 +810,350 @@ impl<T> Cache<T> for RedisCache
 where
     T: serde::Serialize + serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
 {
-    async fn insert_item(&self, key: String, value: T, ttl: u64) -> io::Result<()> {
-        let mut conn = self
-            .pool
-            .get()
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-        let value = serde_json::to_string(&value)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
-        conn.set_ex(key, value, ttl as usize as u64)
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-        Ok(())
+    async fn insert_item(&self, key: String, value: T, ttl: u64) -> CacheResult<()> {
+        Cache::<T>::write_item(
+            self,
+            key,
+            value,
+            Some(ttl),
+            Precondition::Always,
+            &[],
+            false,
+        )
+        .await
+        .map(|_| ())
     }
 
-    async fn retrieve_item(&self, key: &str) -> Option<T>
+    async fn retrieve_item(&self, key: &str) -> CacheResult<Option<T>>
     where
         T: Clone,
     {
-        let mut conn = self.pool.get().await.ok()?;
-        let value: Option<String> = conn.get(key).await.ok()?;
-        value.and_then(|v| serde_json::from_str(&v).ok())
+        Ok(Cache::<T>::retrieve_versioned(self, key)
+            .await?
+            .map(|(value, _)| value))
     }
 
-    async fn remove_item(&self, key: &str) -> io::Result<()> {
-        let mut conn = self
-            .pool
-            .get()
+    async fn retrieve_versioned(&self, key: &str) -> CacheResult<Option<(T, u64)>> {
+        Ok(Cache::<T>::retrieve_entry(self, key)
+            .await?
+            .map(|entry| (entry.value, entry.version)))
+    }
+
+    /// Reads with `GET` and `PTTL`; a sliding value is then read again with
+    /// `GETEX key EX <ttl>`, which restarts its TTL. Should the key be
+    /// rewritten in between, the new value gets the old sliding TTL.
+    async fn retrieve_entry(&self, key: &str) -> CacheResult<Option<EntryInfo<T>>> {
+        let mut pipe = redis::pipe();
+        if !self.is_cluster() {
+            pipe.atomic();
+        }
+        pipe.get(key).pttl(key);
+        let (stored, ttl): (Option<String>, i64) = self.query(&pipe).await?;
+        let Some(mut stored) = stored else {
+            return Ok(None);
+        };
+        let mut ttl = u64::try_from(ttl).ok().map(|ms| ms.div_ceil(1000));
+        if let Some(window) = sliding_window(&stored) {
+            let Some(slid) = self.slide(&[(key, window)]).await?.pop().flatten() else {
+                return Ok(None);
+            };
+            stored = slid;
+            ttl = Some(window);
+        }
+        let (value, version) = decode(&stored)?;
+        Ok(Some(EntryInfo {
+            value,
+            version,
+            ttl,
+        }))
+    }
+
+    /// Runs `WRITE_SCRIPT`, which checks the condition, writes and publishes
+    /// the invalidation atomically.
+    async fn write_item(
+        &self,
+        key: String,
+        value: T,
+        ttl: Option<u64>,
+        condition: Precondition,
+        tags: &[String],
+        sliding: bool,
+    ) -> CacheResult<Option<u64>> {
+        let value = serde_json::to_string(&value)?;
+        let (ttl_arg, condition, channel, head) = self.script_args(ttl, condition, tags, sliding);
+        let version: u64 = WRITE_SCRIPT
+            .key(&key)
+            .arg(value)
+            .arg(ttl_arg)
+            .arg(condition)
+            .arg(channel)
+            .arg(head)
+            .arg(invalidation::pod_id())
+            .invoke_async(&mut self.connection().await?)
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-        conn.del(key)
+            .map_err(CacheError::from)?;
+        if version == 0 {
+            return Ok(None);
+        }
+        // After the write, so a concurrent invalidation cannot drop the
+        // membership of an entry that is about to carry the tag. Reads may
+        // keep a sliding entry alive indefinitely, so its tag sets never
+        // expire; invalidation prunes them instead.
+        let tag_ttl = ttl.filter(|_| !sliding);
+        self.add_to_tags(&key, tag_ttl, tags).await?;
+        Ok(Some(version))
+    }
+
+    async fn retrieve_ttl(&self, key: &str) -> CacheResult<Option<Option<u64>>> {
+        let ttl = self.ttls(&[key.to_string()]).await?;
+        Ok(match ttl.first() {
+            Some(-2) | None => None,
+            Some(&ms) => Some(u64::try_from(ms).ok().map(|ms| ms.div_ceil(1000))),
+        })
+    }
+
+    /// Tag sets are stretched to outlive the entry's new expiry; they are
+    /// never shortened.
+    async fn set_expiry(&self, key: &str, expiry: Expiry) -> CacheResult<bool> {
+        let (mode, secs, ttl) = match expiry {
+            Expiry::In(secs) => {
+                let secs = secs.min(MAX_TTL_SECS);
+                ("in", secs, Some(secs))
+            }
+            Expiry::At(secs) => {
+                let now = now_millis() / 1000;
+                let secs = secs.min(now + MAX_TTL_SECS);
+                ("at", secs, Some(secs.saturating_sub(now)))
+            }
+            Expiry::Never => ("never", 0, None),
+        };
+        let tags: Option<String> = EXPIRE_SCRIPT
+            .key(key)
+            .arg(mode)
+            .arg(secs)
+            .arg(self.invalidation_channel.as_deref().unwrap_or(""))
+            .arg(invalidation::pod_id())
+            .invoke_async(&mut self.connection().await?)
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-        Ok(())
+            .map_err(CacheError::from)?;
+        let Some(tags) = tags else {
+            return Ok(false);
+        };
+        if ttl != Some(0) && !tags.is_empty() {
+            let tags: Vec<String> = tags.split(',').map(str::to_string).collect();
+            self.add_to_tags(key, ttl, &tags).await?;
+        }
+        Ok(true)
+    }
+
+    /// SSCANs the tag's set and deletes each member whose envelope still
+    /// carries the tag, then drops the scanned members from the set. Members
+    /// whose entries expired or were rewritten without the tag are dropped
+    /// the same way; any left over vanish when the set expires.
+    async fn invalidate_tag(&self, tag: &str) -> CacheResult<u64> {
+        let set = format!("{}{}", TAG_SET_PREFIX, tag);
+        let channel = self.invalidation_channel.as_deref().unwrap_or("");
+        let mut removed = 0;
+        let mut cursor = 0;
+        loop {
+            let mut pipe = redis::pipe();
+            pipe.cmd("SSCAN")
+                .arg(&set)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(REMOVE_SCAN_COUNT);
+            let ((next, members),): ((u64, Vec<String>),) = self.query(&pipe).await?;
+            let calls = members
+                .iter()
+                .map(|key| (key.clone(), vec![tag.to_string(), channel.to_string()]))
+                .collect();
+            let deleted: Vec<u64> = self
+                .eval_each(&TAG_REMOVE_SCRIPT, TAG_REMOVE_SCRIPT_SOURCE, calls)
+                .await?;
+            removed += deleted.iter().sum::<u64>();
+            if !members.is_empty() {
+                self.query::<()>(redis::pipe().srem(&set, &members).ignore())
+                    .await?;
+            }
+            if next == 0 {
+                return Ok(removed);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn increment(&self, key: &str, delta: i64, ttl: Option<u64>) -> CacheResult<i64> {
+        let zero = counter::encode::<T>(0).ok_or(CounterError::NotAnInteger)?;
+        let as_string = serde_json::to_value(zero).is_ok_and(|zero| zero.is_string());
+        INCREMENT_SCRIPT
+            .key(key)
+            .arg(delta)
+            .arg(if as_string { "1" } else { "0" })
+            .arg(
+                ttl.map(|ttl| ttl.min(MAX_TTL_SECS).to_string())
+                    .unwrap_or_default(),
+            )
+            .arg(self.invalidation_channel.as_deref().unwrap_or(""))
+            .arg(invalidation::pod_id())
+            .invoke_async(&mut self.connection().await?)
+            .await
+            .map_err(|e| match e.code() {
+                Some("NOTINT") => CounterError::NotAnInteger.into(),
+                Some("OVERFLOW") => CounterError::Overflow.into(),
+                _ => e.into(),
+            })
+    }
+
+    /// Runs one SCAN round per page, so `limit` is only the COUNT hint:
+    /// pages may be shorter or slightly longer. Keys are not sorted.
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> CacheResult<KeyPage> {
+        let glob = KeyPattern::Prefix(prefix.to_string()).redis_glob();
+        let (keys, cursor) = self.scan_round(cursor, &glob, limit).await?;
+        let ttls = self.ttls(&keys).await?;
+        Ok(KeyPage {
+            keys: key_infos(keys, ttls),
+            cursor,
+        })
+    }
+
+    /// SCANs in rounds of `REMOVE_SCAN_COUNT` keys and deletes each round's
+    /// keys before the next, publishing their invalidations, so Redis is
+    /// never busy with one long command.
+    async fn remove_matching(&self, pattern: &KeyPattern) -> CacheResult<u64> {
+        let glob = pattern.redis_glob();
+        let mut removed = 0;
+        let mut cursor = None;
+        loop {
+            let (keys, next) = self
+                .scan_round(cursor.as_deref(), &glob, REMOVE_SCAN_COUNT)
+                .await?;
+            if !keys.is_empty() {
+                removed += self.delete(&keys).await?;
+            }
+            match next {
+                Some(next) => cursor = Some(next),
+                None => return Ok(removed),
+            }
+        }
     }
 
-    async fn invalidate_expired(&self, _interval: Duration) {
-        info!("Redis handles expiration internally, no need to manually invalidate")
+    async fn remove_item(&self, key: &str) -> CacheResult<()> {
+        let mut pipe = redis::pipe();
+        pipe.del(key).ignore();
+        self.write(pipe, &[key]).await
+    }
+
+    async fn retrieve_items(&self, keys: &[String]) -> Vec<CacheResult<Option<T>>> {
+        if keys.is_empty() {
+            return Vec::new();
+        }
+        if self.is_cluster() {
+            return join_all(keys.iter().map(|key| Cache::<T>::retrieve_item(self, key))).await;
+        }
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.get(key);
+        }
+        let mut values: Vec<Option<String>> = match self.query(&pipe).await {
+            Ok(values) => values,
+            Err(e) => return keys.iter().map(|_| Err(e.clone())).collect(),
+        };
+        let (positions, sliding): (Vec<usize>, Vec<(&str, u64)>) = values
+            .iter()
+            .enumerate()
+            .filter_map(|(position, value)| {
+                let window = sliding_window(value.as_deref()?)?;
+                Some((position, (keys[position].as_str(), window)))
+            })
+            .unzip();
+        if !sliding.is_empty() {
+            match self.slide(&sliding).await {
+                Ok(slid) => {
+                    for (position, value) in positions.into_iter().zip(slid) {
+                        values[position] = value;
+                    }
+                }
+                Err(e) => return keys.iter().map(|_| Err(e.clone())).collect(),
+            }
+        }
+        values
+            .into_iter()
+            .map(|value| {
+                let decoded = value.map(|v| decode(&v)).transpose()?;
+                Ok(decoded.map(|(value, _)| value))
+            })
+            .collect()
+    }
+
+    async fn insert_items(&self, items: Vec<(String, T, u64)>) -> Vec<CacheResult<()>> {
+        if self.is_cluster() {
+            return join_all(
+                items
+                    .into_iter()
+                    .map(|(key, value, ttl)| Cache::<T>::insert_item(self, key, value, ttl)),
+            )
+            .await;
+        }
+        let mut results = Vec::with_capacity(items.len());
+        let mut pipe = redis::pipe();
+        // Loading first lets every EVALSHA below find the script.
+        pipe.cmd("SCRIPT")
+            .arg("LOAD")
+            .arg(WRITE_SCRIPT_SOURCE)
+            .ignore();
+        let mut written = 0;
+        for (key, value, ttl) in &items {
+            match serde_json::to_string(value) {
+                Ok(value) => {
+                    let (ttl, condition, channel, head) =
+                        self.script_args(Some(*ttl), Precondition::Always, &[], false);
+                    pipe.cmd("EVALSHA")
+                        .arg(WRITE_SCRIPT.get_hash())
+                        .arg(1)
+                        .arg(key)
+                        .arg(value)
+                        .arg(ttl)
+                        .arg(condition)
+                        .arg(channel)
+                        .arg(head)
+                        .arg(invalidation::pod_id())
+                        .ignore();
+                    written += 1;
+                    results.push(Ok(()));
+                }
+                Err(e) => results.push(Err(e.into())),
+            }
+        }
+        if written == 0 {
+            return results;
+        }
+        if let Err(e) = self.query::<()>(&pipe).await {
+            fail_all(&mut results, &e);
+        }
+        results
+    }
+
+    async fn remove_items(&self, keys: &[String]) -> Vec<CacheResult<()>> {
+        if keys.is_empty() {
+            return Vec::new();
+        }
+        if self.is_cluster() {
+            return join_all(keys.iter().map(|key| Cache::<T>::remove_item(self, key))).await;
+        }
+        let mut results: Vec<CacheResult<()>> = keys.iter().map(|_| Ok(())).collect();
+        if let Err(e) = self.delete(keys).await {
+            fail_all(&mut results, &e);
+        }
+        results
+    }
+
+    async fn invalidate_expired(&self) {
+        debug!("Redis handles expiration internally, no need to manually invalidate")
     }
 }
 
@@ -68,9 +1163,9 @@ mod tests {
     use bb8::Pool;
     use bb8_redis::RedisConnectionManager;
     use dotenv::dotenv;
+    use redis::AsyncCommands;
     use serde::{Deserialize, Serialize};
     use std::env;
-    use std::io;
     use tokio;
 
     #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -86,7 +1181,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_insert_item() -> io::Result<()> {
+    async fn test_insert_item() -> CacheResult<()> {
         let pool = get_redis_pool().await;
         let cache = RedisCache::new(pool);
         let key = "test_key".to_string();
@@ -97,7 +1192,7 @@ mod tests {
 
         cache.insert_item(key.clone(), value.clone(), ttl).await?;
 
-        let retrieved_value: TestData = cache.retrieve_item(&key).await.unwrap();
+        let retrieved_value: TestData = cache.retrieve_item(&key).await?.unwrap();
 
         assert_eq!(value, retrieved_value);
         Ok(())
@@ -106,20 +1201,355 @@ mod tests {
     #[tokio::test]
     async fn test_retrieve_item() {
         let pool = get_redis_pool().await;
-        let cache = RedisCache::new(pool);
+        let cache = RedisCache::new(pool.clone());
         let key = "test_key".to_string();
         let value = TestData {
             value: "test_value".to_string(),
         };
 
-        let mut conn = cache.pool.get().await.unwrap();
+        let mut conn = pool.get().await.unwrap();
         let _: () = conn
             .set(&key, serde_json::to_string(&value).unwrap())
             .await
             .unwrap();
 
-        let retrieved_value: TestData = cache.retrieve_item(&key).await.unwrap();
+        let retrieved_value: TestData = cache.retrieve_item(&key).await.unwrap().unwrap();
 
         assert_eq!(value, retrieved_value);
     }
+
+    #[tokio::test]
+    async fn test_compare_and_swap() -> CacheResult<()> {
+        let cache = RedisCache::new(get_redis_pool().await);
+        let key = "test_cas_key".to_string();
+        let value = |value: &str| TestData {
+            value: value.to_string(),
+        };
+        Cache::<TestData>::remove_item(&cache, &key).await?;
+
+        let first = cache
+            .write_item(
+                key.clone(),
+                value("a"),
+                Some(10),
+                Precondition::Absent,
+                &[],
+                false,
+            )
+            .await?
+            .unwrap();
+        let conflict = cache
+            .write_item(
+                key.clone(),
+                value("b"),
+                Some(10),
+                Precondition::Absent,
+                &[],
+                false,
+            )
+            .await?;
+        assert_eq!(conflict, None);
+        let second = cache
+            .write_item(
+                key.clone(),
+                value("b"),
+                Some(10),
+                Precondition::Version(first),
+                &[],
+                false,
+            )
+            .await?
+            .unwrap();
+        assert!(second > first);
+        let stale = cache
+            .write_item(
+                key.clone(),
+                value("c"),
+                Some(10),
+                Precondition::Version(first),
+                &[],
+                false,
+            )
+            .await?;
+        assert_eq!(stale, None);
+        assert_eq!(
+            cache.retrieve_versioned(&key).await.unwrap(),
+            Some((value("b"), second))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_increment() -> CacheResult<()> {
+        let cache = RedisCache::new(get_redis_pool().await);
+        Cache::<String>::remove_item(&cache, "test_counter").await?;
+
+        assert_eq!(
+            Cache::<String>::increment(&cache, "test_counter", 5, Some(10)).await?,
+            5
+        );
+        assert_eq!(
+            Cache::<String>::increment(&cache, "test_counter", -2, None).await?,
+            3
+        );
+        let value: Option<String> = cache.retrieve_item("test_counter").await.unwrap();
+        assert_eq!(value, Some("3".to_string()));
+
+        cache
+            .insert_item("test_counter".to_string(), "abc".to_string(), 10)
+            .await?;
+        let err = Cache::<String>::increment(&cache, "test_counter", 1, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, CacheError::Counter(CounterError::NotAnInteger));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scan_keys() -> CacheResult<()> {
+        let cache = RedisCache::new(get_redis_pool().await);
+        for i in 0..20 {
+            cache
+                .insert_item(format!("test_scan:{}", i), "v".to_string(), 10)
+                .await?;
+        }
+        Cache::<String>::increment(&cache, "test_scan:counter", 1, None).await?;
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page =
+                Cache::<String>::scan_keys(&cache, "test_scan:", cursor.as_deref(), 5).await?;
+            listed.extend(page.keys);
+            match page.cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        listed.sort_by(|a, b| a.key.cmp(&b.key));
+        listed.dedup();
+        assert_eq!(listed.len(), 21);
+        assert_eq!(listed[0].ttl, None);
+        assert!(listed[1..].iter().all(|info| info.ttl == Some(10)));
+        Cache::<String>::remove_item(&cache, "test_scan:counter").await
+    }
+
+    #[tokio::test]
+    async fn test_remove_matching() -> CacheResult<()> {
+        let cache = RedisCache::new(get_redis_pool().await);
+        for i in 0..30 {
+            cache
+                .insert_item(format!("test_bulk:{}:a", i), "v".to_string(), 10)
+                .await?;
+        }
+        cache
+            .insert_item("test_bulk:keep".to_string(), "v".to_string(), 10)
+            .await?;
+
+        let pattern = KeyPattern::Glob("test_bulk:*:a".to_string());
+        assert_eq!(
+            Cache::<String>::remove_matching(&cache, &pattern).await?,
+            30
+        );
+        let kept: Option<String> = cache.retrieve_item("test_bulk:keep").await.unwrap();
+        assert_eq!(kept, Some("v".to_string()));
+        let removed: Option<String> = cache.retrieve_item("test_bulk:3:a").await.unwrap();
+        assert_eq!(removed, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_unavailable() {
+        // Nothing listens on port 1, so every connection attempt is refused.
+        let manager = RedisConnectionManager::new("redis://127.0.0.1:1").unwrap();
+        let pool = Pool::builder()
+            .connection_timeout(std::time::Duration::from_millis(200))
+            .build(manager)
+            .await
+            .unwrap();
+        let cache = RedisCache::new(pool);
+
+        let read = Cache::<String>::retrieve_item(&cache, "key").await;
+        assert!(
+            matches!(read, Err(CacheError::Unavailable(_))),
+            "{:?}",
+            read
+        );
+        let values = Cache::<String>::retrieve_items(&cache, &["a".to_string()]).await;
+        assert!(matches!(values[0], Err(CacheError::Unavailable(_))));
+    }
+
+    #[test]
+    fn test_decode_rejects_undecodable_values() {
+        assert!(matches!(
+            decode::<i64>("7:\"a\""),
+            Err(CacheError::Serialization(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_reads_tagged_and_legacy_values() {
+        assert_eq!(decode::<String>("7:\"a\""), Ok(("a".to_string(), 7)));
+        assert_eq!(
+            decode::<String>("7,user:1,feed\n\"a\""),
+            Ok(("a".to_string(), 7))
+        );
+        assert_eq!(decode::<String>("7~60:\"a\""), Ok(("a".to_string(), 7)));
+        assert_eq!(
+            decode::<String>("7~60,user\n\"a\""),
+            Ok(("a".to_string(), 7))
+        );
+        assert_eq!(decode::<i64>("42"), Ok((42, 0)));
+        assert_eq!(
+            decode::<TestData>("{\"value\":\"x\"}"),
+            Ok((
+                TestData {
+                    value: "x".to_string()
+                },
+                0
+            ))
+        );
+    }
+
+    #[test]
+    fn test_sliding_window_only_reads_sliding_envelopes() {
+        assert_eq!(sliding_window("7~60:\"a\""), Some(60));
+        assert_eq!(sliding_window("7~60,user\n\"a\""), Some(60));
+        assert_eq!(sliding_window("7:\"~60\""), None);
+        assert_eq!(sliding_window("7,user\n\"a\""), None);
+        assert_eq!(sliding_window("~60"), None);
+        assert_eq!(sliding_window("42"), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_tag() -> CacheResult<()> {
+        let cache = RedisCache::new(get_redis_pool().await);
+        let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+        let write = |key: &str, tags: Vec<String>| {
+            let cache = &cache;
+            let key = key.to_string();
+            async move {
+                Cache::<String>::write_item(
+                    cache,
+                    key,
+                    "v".to_string(),
+                    Some(10),
+                    Precondition::Always,
+                    &tags,
+                    false,
+                )
+                .await
+            }
+        };
+        write("test_tag:a", tags(&["test_user:1", "test_feed"])).await?;
+        write("test_tag:b", tags(&["test_user:1"])).await?;
+        write("test_tag:c", tags(&["test_feed"])).await?;
+        write("test_tag:b", tags(&[])).await?;
+
+        assert_eq!(
+            Cache::<String>::invalidate_tag(&cache, "test_user:1").await?,
+            1
+        );
+        let a: Option<String> = cache.retrieve_item("test_tag:a").await.unwrap();
+        let b: Option<String> = cache.retrieve_item("test_tag:b").await.unwrap();
+        assert_eq!((a, b), (None, Some("v".to_string())));
+        assert_eq!(
+            Cache::<String>::invalidate_tag(&cache, "test_feed").await?,
+            1
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_expiry() -> CacheResult<()> {
+        let cache = RedisCache::new(get_redis_pool().await);
+        let key = "test_expiry_key";
+        let tags = vec!["test_expiry_tag".to_string()];
+        Cache::<String>::write_item(
+            &cache,
+            key.to_string(),
+            "v".to_string(),
+            Some(10),
+            Precondition::Always,
+            &tags,
+            false,
+        )
+        .await?;
+
+        assert!(Cache::<String>::set_expiry(&cache, key, Expiry::In(100)).await?);
+        assert!(matches!(
+            Cache::<String>::retrieve_ttl(&cache, key).await?,
+            Some(Some(99..=100))
+        ));
+        assert!(Cache::<String>::set_expiry(&cache, key, Expiry::Never).await?);
+        assert_eq!(
+            Cache::<String>::retrieve_ttl(&cache, key).await?,
+            Some(None)
+        );
+        let mut conn = cache.connection().await?;
+        let set_ttl: i64 = conn.ttl("__tag:test_expiry_tag").await.unwrap();
+        assert_eq!(set_ttl, -1);
+
+        assert!(Cache::<String>::set_expiry(&cache, key, Expiry::At(1)).await?);
+        assert_eq!(Cache::<String>::retrieve_ttl(&cache, key).await?, None);
+        assert!(!Cache::<String>::set_expiry(&cache, key, Expiry::In(10)).await?);
+        Cache::<String>::invalidate_tag(&cache, "test_expiry_tag").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sliding_read_restarts_ttl() -> CacheResult<()> {
+        let cache = RedisCache::new(get_redis_pool().await);
+        let key = "test_sliding_key";
+        Cache::<String>::write_item(
+            &cache,
+            key.to_string(),
+            "v".to_string(),
+            Some(100),
+            Precondition::Always,
+            &[],
+            true,
+        )
+        .await?;
+        let mut conn = cache.connection().await?;
+        let _: bool = conn.expire(key, 5).await.unwrap();
+
+        let value: Option<String> = cache.retrieve_item(key).await.unwrap();
+        assert_eq!(value, Some("v".to_string()));
+        assert!(matches!(
+            Cache::<String>::retrieve_ttl(&cache, key).await?,
+            Some(Some(99..=100))
+        ));
+        let values: Vec<CacheResult<Option<String>>> =
+            cache.retrieve_items(&[key.to_string()]).await;
+        assert_eq!(values, vec![Ok(Some("v".to_string()))]);
+
+        assert!(Cache::<String>::set_expiry(&cache, key, Expiry::In(5)).await?);
+        let _: Option<String> = cache.retrieve_item(key).await.unwrap();
+        assert!(matches!(
+            Cache::<String>::retrieve_ttl(&cache, key).await?,
+            Some(Some(4..=5))
+        ));
+        Cache::<String>::remove_item(&cache, key).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_publishes_invalidation() -> CacheResult<()> {
+        use futures_util::StreamExt;
+
+        let client = redis::Client::open(env::var("TEST_REDIS_URL").unwrap()).unwrap();
+        let mut pubsub = client.get_async_pubsub().await.unwrap();
+        pubsub.subscribe("test_invalidations").await.unwrap();
+        let cache = RedisCache::new(get_redis_pool().await).with_invalidation("test_invalidations");
+
+        Cache::<TestData>::remove_item(&cache, "test_key").await?;
+
+        let message = pubsub.on_message().next().await.unwrap();
+        assert_eq!(
+            message.get_payload::<String>().unwrap(),
+            invalidation::message(invalidation::pod_id(), "test_key")
+        );
+        Ok(())
+    }
 }
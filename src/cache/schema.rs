@@ -1,13 +1,238 @@
+use super::error::{CacheError, CacheResult};
+use super::pattern::KeyPattern;
 use async_trait::async_trait;
-use std::io;
-use tokio::time::Duration;
+
+/// Longest TTL in seconds a backend honours, about 50 years; longer ones are
+/// cut to it. The handlers reject them outright.
+pub const MAX_TTL_SECS: u64 = 50 * 365 * 24 * 60 * 60;
+
+/// Keys starting with this prefix hold backend bookkeeping, such as the tag
+/// sets of the Redis backend. The handlers reject them.
+pub const RESERVED_KEY_PREFIX: &str = "__tag:";
+
+/// Condition a conditional write checks atomically against the current entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// Write unconditionally.
+    Always,
+    /// Write only if the key has no live entry (`If-None-Match: *`).
+    Absent,
+    /// Write only if the key has a live entry (`If-Match: *`).
+    Present,
+    /// Write only if the live entry has this version (`If-Match: "<version>"`).
+    Version(u64),
+}
+
+/// New expiry for an existing entry, set by `Cache::set_expiry`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expiry {
+    /// Expire this many seconds from now.
+    In(u64),
+    /// Expire at this Unix time, in seconds. A time already past removes the
+    /// entry.
+    At(u64),
+    /// Never expire.
+    Never,
+}
+
+/// A key listed by `Cache::scan_keys`, with its remaining TTL in seconds,
+/// rounded up; `None` if the key does not expire.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub key: String,
+    pub ttl: Option<u64>,
+}
+
+/// A live entry as `Cache::retrieve_entry` reads it: its value, its version
+/// (0 if the backend does not version entries) and its remaining TTL in
+/// seconds, rounded up; `None` if it never expires or the backend cannot
+/// tell.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryInfo<T> {
+    pub value: T,
+    pub version: u64,
+    pub ttl: Option<u64>,
+}
+
+/// One page of a key scan. `cursor` resumes the scan, and is `None` once it
+/// is complete.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyPage {
+    pub keys: Vec<KeyInfo>,
+    pub cursor: Option<String>,
+}
 
 #[async_trait]
 pub trait Cache<T>: Send + Sync {
-    async fn insert_item(&self, key: String, value: T, ttl: u64) -> io::Result<()>;
-    async fn retrieve_item(&self, key: &str) -> Option<T>
+    async fn insert_item(&self, key: String, value: T, ttl: u64) -> CacheResult<()>;
+    /// The live value of `key`, or `None` on a miss. A backend that cannot
+    /// answer fails instead, so a miss always means the key is absent.
+    async fn retrieve_item(&self, key: &str) -> CacheResult<Option<T>>
     where
         T: Clone;
-    async fn remove_item(&self, key: &str) -> io::Result<()>;
-    async fn invalidate_expired(&self, interval: Duration);
+    async fn remove_item(&self, key: &str) -> CacheResult<()>;
+    /// Writes `value` if `condition` holds and returns the entry's new
+    /// version, or `None` if the condition failed. Versions only grow, and
+    /// are never reused for a key after it is deleted. Version 0 means the
+    /// backend does not version entries; such backends only accept
+    /// `Precondition::Always`.
+    ///
+    /// The entry expires after `ttl` seconds, or never if `ttl` is `None`;
+    /// callers reject a `ttl` of 0. The entry carries `tags`, replacing any it
+    /// had, so that `invalidate_tag` can find it. Tags are non-empty and
+    /// contain neither `,` nor control characters. If `sliding`, every read
+    /// restarts the `ttl` countdown.
+    async fn write_item(
+        &self,
+        key: String,
+        value: T,
+        ttl: Option<u64>,
+        condition: Precondition,
+        tags: &[String],
+        sliding: bool,
+    ) -> CacheResult<Option<u64>>
+    where
+        T: Send + 'async_trait,
+    {
+        if !tags.is_empty() {
+            return Err(CacheError::Unsupported(
+                "tags are not supported by this backend",
+            ));
+        }
+        if sliding {
+            return Err(CacheError::Unsupported(
+                "sliding expiration is not supported by this backend",
+            ));
+        }
+        let Some(ttl) = ttl else {
+            return Err(CacheError::Unsupported(
+                "entries without expiry are not supported by this backend",
+            ));
+        };
+        match condition {
+            Precondition::Always => self.insert_item(key, value, ttl).await.map(|()| Some(0)),
+            _ => Err(CacheError::Unsupported(
+                "conditional writes are not supported by this backend",
+            )),
+        }
+    }
+    /// Removes every live entry carrying `tag` and returns how many were
+    /// removed. Like `remove_matching`, it works in small batches, and
+    /// entries tagged meanwhile may survive.
+    async fn invalidate_tag(&self, _tag: &str) -> CacheResult<u64> {
+        Err(CacheError::Unsupported(
+            "tags are not supported by this backend",
+        ))
+    }
+    /// Adds `delta` to the integer stored at `key`, atomically, and returns
+    /// the result. A missing key counts as 0 and is created with `ttl`, or
+    /// without expiry if `ttl` is `None`; an existing key keeps its TTL.
+    /// Fails with `CacheError::Counter` if the value is not an integer or the
+    /// result overflows.
+    async fn increment(&self, _key: &str, _delta: i64, _ttl: Option<u64>) -> CacheResult<i64> {
+        Err(CacheError::Unsupported(
+            "counters are not supported by this backend",
+        ))
+    }
+    /// Remaining TTL of `key` in seconds, rounded up: `None` if there is no
+    /// live entry, `Some(None)` if it never expires.
+    async fn retrieve_ttl(&self, _key: &str) -> CacheResult<Option<Option<u64>>> {
+        Err(CacheError::Unsupported(
+            "TTL management is not supported by this backend",
+        ))
+    }
+    /// Changes when `key` expires without rewriting its value or version. A
+    /// sliding entry gets this fixed expiry and stops sliding. Returns `false`
+    /// if there is no live entry.
+    async fn set_expiry(&self, _key: &str, _expiry: Expiry) -> CacheResult<bool> {
+        Err(CacheError::Unsupported(
+            "TTL management is not supported by this backend",
+        ))
+    }
+    /// Lists live keys starting with `prefix`, at most `limit` at a time,
+    /// resuming after `cursor` from the previous page. Cursors are opaque and
+    /// only valid for the backend that issued them; a malformed one is
+    /// `CacheError::InvalidInput`. A page may hold fewer than `limit` keys, or none,
+    /// before the scan is complete. Keys written during a scan may or may not
+    /// be listed.
+    async fn scan_keys(
+        &self,
+        _prefix: &str,
+        _cursor: Option<&str>,
+        _limit: usize,
+    ) -> CacheResult<KeyPage> {
+        Err(CacheError::Unsupported(
+            "key listing is not supported by this backend",
+        ))
+    }
+    /// Removes every live key matching `pattern` and returns how many were
+    /// removed. Works in small batches, so other requests keep being served
+    /// while a large removal runs; keys written meanwhile may survive.
+    async fn remove_matching(&self, _pattern: &KeyPattern) -> CacheResult<u64> {
+        Err(CacheError::Unsupported(
+            "bulk removal is not supported by this backend",
+        ))
+    }
+    /// Like `retrieve_item`, together with the entry's version (0 if the
+    /// backend does not version entries).
+    async fn retrieve_versioned(&self, key: &str) -> CacheResult<Option<(T, u64)>>
+    where
+        T: Clone + Send,
+    {
+        Ok(self.retrieve_item(key).await?.map(|value| (value, 0)))
+    }
+    /// Like `retrieve_versioned`, together with the remaining TTL, all from
+    /// one read so that they describe the same entry.
+    async fn retrieve_entry(&self, key: &str) -> CacheResult<Option<EntryInfo<T>>>
+    where
+        T: Clone + Send,
+    {
+        Ok(self
+            .retrieve_versioned(key)
+            .await?
+            .map(|(value, version)| EntryInfo {
+                value,
+                version,
+                ttl: None,
+            }))
+    }
+    /// Looks up every key in `keys` and returns one result per key, in order.
+    async fn retrieve_items(&self, keys: &[String]) -> Vec<CacheResult<Option<T>>>
+    where
+        T: Clone + Send,
+    {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.retrieve_item(key).await);
+        }
+        values
+    }
+    /// Writes every `(key, value, ttl)` item and returns one result per item,
+    /// in order.
+    async fn insert_items(&self, items: Vec<(String, T, u64)>) -> Vec<CacheResult<()>>
+    where
+        T: Send + 'async_trait,
+    {
+        let mut results = Vec::with_capacity(items.len());
+        for (key, value, ttl) in items {
+            results.push(self.insert_item(key, value, ttl).await);
+        }
+        results
+    }
+    /// Removes every key in `keys` and returns one result per key, in order.
+    async fn remove_items(&self, keys: &[String]) -> Vec<CacheResult<()>> {
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            results.push(self.remove_item(key).await);
+        }
+        results
+    }
+    /// Runs one bounded pass removing expired entries. Scheduling the passes
+    /// is the job of `maintenance::Maintenance`.
+    async fn invalidate_expired(&self);
+    /// Writes whatever state must outlive the process. Called periodically and
+    /// on shutdown by `maintenance::Maintenance`; a no-op by default.
+    async fn persist(&self) -> CacheResult<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,177 @@
+use super::metrics::{COALESCED_COUNTER, FLIGHT_TIMEOUT_COUNTER};
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Flight<T> = Shared<BoxFuture<'static, Option<T>>>;
+type Flights<T> = Arc<Mutex<HashMap<String, Flight<T>>>>;
+
+/// Why `SingleFlight::run` returned without a result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlightError {
+    /// The wait bound elapsed first. The flight carries on, and callers
+    /// arriving later still join it.
+    TimedOut,
+    /// The work panicked.
+    Aborted,
+}
+
+impl fmt::Display for FlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlightError::TimedOut => write!(f, "timed out waiting for the fetch"),
+            FlightError::Aborted => write!(f, "the fetch panicked"),
+        }
+    }
+}
+
+impl std::error::Error for FlightError {}
+
+/// Request coalescing: concurrent calls for the same key share one run of
+/// the work, and all receive its result.
+///
+/// The work runs as its own task, so it finishes, and can cache what it
+/// fetched, even if every caller stops waiting. The key is free again as
+/// soon as the work is done; calls after that run it anew. Waits are counted
+/// in `coalesced_requests` and timeouts in `flight_timeouts`, both labelled
+/// with `name`.
+pub struct SingleFlight<T> {
+    name: &'static str,
+    flights: Flights<T>,
+}
+
+/// Frees the key when the work ends, even by panicking.
+struct Landing<T> {
+    flights: Flights<T>,
+    key: String,
+}
+
+impl<T> Drop for Landing<T> {
+    fn drop(&mut self) {
+        if let Ok(mut flights) = self.flights.lock() {
+            flights.remove(&self.key);
+        }
+    }
+}
+
+impl<T> SingleFlight<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            flights: Arc::default(),
+        }
+    }
+
+    /// Runs `work` for `key`, or joins the run already in flight for it, and
+    /// waits at most `max_wait` for the result.
+    pub async fn run<F>(&self, key: &str, max_wait: Duration, work: F) -> Result<T, FlightError>
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let flight = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(key) {
+                Some(flight) => {
+                    COALESCED_COUNTER.with_label_values(&[self.name]).inc();
+                    flight.clone()
+                }
+                None => {
+                    let landing = Landing {
+                        flights: Arc::clone(&self.flights),
+                        key: key.to_string(),
+                    };
+                    let task = tokio::spawn(async move {
+                        let _landing = landing;
+                        work.await
+                    });
+                    let flight = async move { task.await.ok() }.boxed().shared();
+                    flights.insert(key.to_string(), flight.clone());
+                    flight
+                }
+            }
+        };
+        match tokio::time::timeout(max_wait, flight).await {
+            Ok(Some(result)) => Ok(result),
+            Ok(None) => Err(FlightError::Aborted),
+            Err(_) => {
+                FLIGHT_TIMEOUT_COUNTER.with_label_values(&[self.name]).inc();
+                Err(FlightError::TimedOut)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counted(runs: &Arc<AtomicUsize>, delay: Duration) -> impl Future<Output = usize> {
+        let runs = Arc::clone(runs);
+        async move {
+            tokio::time::sleep(delay).await;
+            runs.fetch_add(1, Ordering::SeqCst) + 1
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_run() {
+        let flights = Arc::new(SingleFlight::new("test"));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let waiters: Vec<_> = (0..10)
+            .map(|_| {
+                let flights = Arc::clone(&flights);
+                let work = counted(&runs, Duration::from_millis(50));
+                tokio::spawn(async move { flights.run("k", Duration::from_secs(1), work).await })
+            })
+            .collect();
+        for waiter in waiters {
+            assert_eq!(waiter.await.unwrap(), Ok(1));
+        }
+
+        let work = counted(&runs, Duration::ZERO);
+        assert_eq!(flights.run("k", Duration::from_secs(1), work).await, Ok(2));
+        let work = counted(&runs, Duration::ZERO);
+        assert_eq!(
+            flights.run("other", Duration::from_secs(1), work).await,
+            Ok(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_waits_are_bounded_and_the_run_completes() {
+        let flights = SingleFlight::new("test");
+        let runs = Arc::new(AtomicUsize::new(0));
+        let work = counted(&runs, Duration::from_millis(100));
+        assert_eq!(
+            flights.run("k", Duration::from_millis(10), work).await,
+            Err(FlightError::TimedOut)
+        );
+
+        let unused = counted(&runs, Duration::ZERO);
+        assert_eq!(
+            flights.run("k", Duration::from_secs(1), unused).await,
+            Ok(1)
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_a_panicking_run_frees_the_key() {
+        let flights = SingleFlight::new("test");
+        let result = flights
+            .run("k", Duration::from_secs(1), async { panic!("boom") })
+            .await;
+        assert_eq!(result, Err::<(), _>(FlightError::Aborted));
+        assert_eq!(
+            flights.run("k", Duration::from_secs(1), async {}).await,
+            Ok(())
+        );
+    }
+}
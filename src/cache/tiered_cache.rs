@@ -0,0 +1,546 @@
+use super::error::CacheResult;
+use super::in_memory_cache::{InMemoryCache, Weigh};
+use super::metrics::{TIER_HIT_COUNTER, TIER_MISS_COUNTER};
+use super::pattern::KeyPattern;
+use super::schema::{Cache, EntryInfo, Expiry, KeyPage, Precondition};
+use async_trait::async_trait;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// An L1 copy together with the version L2 gave it; 0 when the write that
+/// produced it did not report one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    pub value: T,
+    pub version: u64,
+}
+
+impl<T: Weigh> Weigh for Versioned<T> {
+    fn weigh(&self) -> usize {
+        self.value.weigh() + std::mem::size_of::<u64>()
+    }
+}
+
+/// Near-cache composition: a small local `InMemoryCache` (L1) in front of a
+/// shared backend such as `RedisCache` (L2).
+///
+/// L2 is the source of truth; every write goes there first. L1 copies live at
+/// most `l1_ttl_cap` seconds, which bounds how stale a pod can be after
+/// another pod changes the key. `invalidation::subscribe` on the shared L1
+/// evicts such keys as soon as the change is published. L1 keeps the version
+/// of each copy so versioned reads can be served locally too.
+pub struct TieredCache<T> {
+    l1: Arc<InMemoryCache<Versioned<T>>>,
+    l2: Arc<dyn Cache<T>>,
+    l1_ttl_cap: u64,
+}
+
+impl<T> TieredCache<T> {
+    pub fn new(
+        l1: Arc<InMemoryCache<Versioned<T>>>,
+        l2: Arc<dyn Cache<T>>,
+        l1_ttl_cap: u64,
+    ) -> Self {
+        Self { l1, l2, l1_ttl_cap }
+    }
+}
+
+impl<T> TieredCache<T>
+where
+    T: Weigh + Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    /// Stores an L1 copy. If L1 rejects it, any older copy is dropped
+    /// instead, since it no longer matches L2.
+    async fn copy_to_l1(
+        &self,
+        key: &str,
+        value: T,
+        version: u64,
+        ttl: Option<u64>,
+        tags: &[String],
+    ) {
+        let copy = Versioned { value, version };
+        let ttl = ttl.map_or(self.l1_ttl_cap, |ttl| ttl.min(self.l1_ttl_cap));
+        if let Err(e) = self
+            .l1
+            .write_item(
+                key.to_string(),
+                copy,
+                Some(ttl),
+                Precondition::Always,
+                tags,
+                false,
+            )
+            .await
+        {
+            warn!("Not caching {} in L1: {}", key, e);
+            let _ = self.l1.remove_item(key).await;
+        }
+    }
+
+    /// Reads L2 after an L1 miss and copies what it finds into L1.
+    async fn retrieve_from_l2(&self, key: &str) -> CacheResult<Option<EntryInfo<T>>> {
+        TIER_MISS_COUNTER.with_label_values(&["l1"]).inc();
+        let Some(entry) = self.l2.retrieve_entry(key).await? else {
+            TIER_MISS_COUNTER.with_label_values(&["l2"]).inc();
+            return Ok(None);
+        };
+        TIER_HIT_COUNTER.with_label_values(&["l2"]).inc();
+        self.copy_to_l1(key, entry.value.clone(), entry.version, entry.ttl, &[])
+            .await;
+        Ok(Some(entry))
+    }
+}
+
+#[async_trait]
+impl<T> Cache<T> for TieredCache<T>
+where
+    T: Weigh + Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    async fn insert_item(&self, key: String, value: T, ttl: u64) -> CacheResult<()> {
+        self.write_item(key, value, Some(ttl), Precondition::Always, &[], false)
+            .await
+            .map(|_| ())
+    }
+
+    async fn retrieve_item(&self, key: &str) -> CacheResult<Option<T>> {
+        if let Some(copy) = self.l1.retrieve_item(key).await? {
+            TIER_HIT_COUNTER.with_label_values(&["l1"]).inc();
+            return Ok(Some(copy.value));
+        }
+        Ok(self.retrieve_from_l2(key).await?.map(|entry| entry.value))
+    }
+
+    async fn remove_item(&self, key: &str) -> CacheResult<()> {
+        self.l2.remove_item(key).await?;
+        self.l1.remove_item(key).await
+    }
+
+    /// Counted in L2; the L1 copy is dropped rather than updated, so it is
+    /// refilled with the version L2 assigned.
+    async fn increment(&self, key: &str, delta: i64, ttl: Option<u64>) -> CacheResult<i64> {
+        let result = self.l2.increment(key, delta, ttl).await?;
+        self.l1.remove_item(key).await?;
+        Ok(result)
+    }
+
+    /// Listed from L2, which holds every key.
+    async fn scan_keys(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> CacheResult<KeyPage> {
+        self.l2.scan_keys(prefix, cursor, limit).await
+    }
+
+    /// Counts what L2 removed; L1 copies are dropped as well.
+    async fn remove_matching(&self, pattern: &KeyPattern) -> CacheResult<u64> {
+        let removed = self.l2.remove_matching(pattern).await?;
+        self.l1.remove_matching(pattern).await?;
+        Ok(removed)
+    }
+
+    async fn retrieve_ttl(&self, key: &str) -> CacheResult<Option<Option<u64>>> {
+        self.l2.retrieve_ttl(key).await
+    }
+
+    /// The L1 copy is dropped in case the entry now expires before it would.
+    async fn set_expiry(&self, key: &str, expiry: Expiry) -> CacheResult<bool> {
+        let found = self.l2.set_expiry(key, expiry).await?;
+        self.l1.remove_item(key).await?;
+        Ok(found)
+    }
+
+    /// L1 only knows the tags of copies written through this instance; copies
+    /// read from L2 are dropped by invalidation messages, or age out within
+    /// the L1 TTL cap.
+    async fn invalidate_tag(&self, tag: &str) -> CacheResult<u64> {
+        let removed = self.l2.invalidate_tag(tag).await?;
+        self.l1.invalidate_tag(tag).await?;
+        Ok(removed)
+    }
+
+    async fn retrieve_versioned(&self, key: &str) -> CacheResult<Option<(T, u64)>> {
+        Ok(self
+            .retrieve_entry(key)
+            .await?
+            .map(|entry| (entry.value, entry.version)))
+    }
+
+    /// Copies without a known version are skipped, so the ETag always comes
+    /// from L2. An L1 hit reports the TTL of the copy, which never outlives
+    /// the entry in L2.
+    async fn retrieve_entry(&self, key: &str) -> CacheResult<Option<EntryInfo<T>>> {
+        match self.l1.retrieve_entry(key).await? {
+            Some(copy) if copy.value.version > 0 => {
+                TIER_HIT_COUNTER.with_label_values(&["l1"]).inc();
+                Ok(Some(EntryInfo {
+                    value: copy.value.value,
+                    version: copy.value.version,
+                    ttl: copy.ttl,
+                }))
+            }
+            _ => self.retrieve_from_l2(key).await,
+        }
+    }
+
+    /// Conditions are checked by L2. When one fails, the local copy is
+    /// likely stale and is dropped.
+    ///
+    /// Only L2 slides: L1 copies keep a fixed TTL, and L1 hits do not reach
+    /// L2. A sliding entry is still refreshed by the reads that miss L1, at
+    /// least once per L1 TTL cap while it is being read.
+    async fn write_item(
+        &self,
+        key: String,
+        value: T,
+        ttl: Option<u64>,
+        condition: Precondition,
+        tags: &[String],
+        sliding: bool,
+    ) -> CacheResult<Option<u64>> {
+        let written = self
+            .l2
+            .write_item(key.clone(), value.clone(), ttl, condition, tags, sliding)
+            .await?;
+        match written {
+            Some(version) => self.copy_to_l1(&key, value, version, ttl, tags).await,
+            None => self.l1.remove_item(&key).await?,
+        }
+        Ok(written)
+    }
+
+    /// An L1 lookup that fails counts as a miss and is retried in L2.
+    async fn retrieve_items(&self, keys: &[String]) -> Vec<CacheResult<Option<T>>> {
+        let mut values: Vec<CacheResult<Option<T>>> = self
+            .l1
+            .retrieve_items(keys)
+            .await
+            .into_iter()
+            .map(|copy| Ok(copy.ok().flatten().map(|copy| copy.value)))
+            .collect();
+        let missing: Vec<usize> = (0..keys.len())
+            .filter(|&i| matches!(values[i], Ok(None)))
+            .collect();
+        TIER_HIT_COUNTER
+            .with_label_values(&["l1"])
+            .inc_by((keys.len() - missing.len()) as f64);
+        TIER_MISS_COUNTER
+            .with_label_values(&["l1"])
+            .inc_by(missing.len() as f64);
+        if missing.is_empty() {
+            return values;
+        }
+
+        let missing_keys: Vec<String> = missing.iter().map(|&i| keys[i].clone()).collect();
+        let mut fills = Vec::new();
+        let mut failed = 0;
+        for (i, value) in missing
+            .into_iter()
+            .zip(self.l2.retrieve_items(&missing_keys).await)
+        {
+            if let Ok(Some(value)) = &value {
+                let copy = Versioned {
+                    value: value.clone(),
+                    version: 0,
+                };
+                fills.push((keys[i].clone(), copy, self.l1_ttl_cap));
+            }
+            failed += value.is_err() as usize;
+            values[i] = value;
+        }
+        TIER_HIT_COUNTER
+            .with_label_values(&["l2"])
+            .inc_by(fills.len() as f64);
+        TIER_MISS_COUNTER
+            .with_label_values(&["l2"])
+            .inc_by((missing_keys.len() - fills.len() - failed) as f64);
+        let filled: Vec<String> = fills.iter().map(|(key, _, _)| key.clone()).collect();
+        for (key, result) in filled.iter().zip(self.l1.insert_items(fills).await) {
+            if let Err(e) = result {
+                warn!("Not caching {} in L1: {}", key, e);
+            }
+        }
+        values
+    }
+
+    async fn insert_items(&self, items: Vec<(String, T, u64)>) -> Vec<CacheResult<()>> {
+        let results = self.l2.insert_items(items.clone()).await;
+        let copies: Vec<_> = items
+            .into_iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok())
+            .map(|((key, value, ttl), _)| {
+                let copy = Versioned { value, version: 0 };
+                (key, copy, ttl.min(self.l1_ttl_cap))
+            })
+            .collect();
+        let keys: Vec<String> = copies.iter().map(|(key, _, _)| key.clone()).collect();
+        let mut rejected = Vec::new();
+        for (key, result) in keys.into_iter().zip(self.l1.insert_items(copies).await) {
+            if let Err(e) = result {
+                warn!("Not caching {} in L1: {}", key, e);
+                rejected.push(key);
+            }
+        }
+        self.l1.remove_items(&rejected).await;
+        results
+    }
+
+    async fn remove_items(&self, keys: &[String]) -> Vec<CacheResult<()>> {
+        let results = self.l2.remove_items(keys).await;
+        self.l1.remove_items(keys).await;
+        results
+    }
+
+    async fn invalidate_expired(&self) {
+        self.l1.invalidate_expired().await;
+        self.l2.invalidate_expired().await;
+    }
+
+    async fn persist(&self) -> CacheResult<()> {
+        self.l2.persist().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::InMemoryConfig;
+    use tokio::time::{self, Duration};
+
+    fn tiered(l1_ttl_cap: u64) -> (TieredCache<String>, Arc<InMemoryCache<String>>) {
+        let l2 = Arc::new(InMemoryCache::new());
+        let l1 = Arc::new(InMemoryCache::with_config(InMemoryConfig {
+            max_entries: Some(2),
+            ..Default::default()
+        }));
+        let cache = TieredCache::new(l1, Arc::clone(&l2) as Arc<dyn Cache<String>>, l1_ttl_cap);
+        (cache, l2)
+    }
+
+    #[tokio::test]
+    async fn test_writes_reach_both_tiers() {
+        let (cache, l2) = tiered(60);
+        cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+
+        assert_eq!(
+            cache
+                .l1
+                .retrieve_item("a")
+                .await
+                .unwrap()
+                .map(|copy| copy.value),
+            Some("1".to_string())
+        );
+        assert_eq!(l2.retrieve_item("a").await.unwrap(), Some("1".to_string()));
+
+        cache.remove_item("a").await.unwrap();
+        assert_eq!(cache.l1.retrieve_item("a").await.unwrap(), None);
+        assert_eq!(l2.retrieve_item("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_l2_hit_populates_l1() {
+        let (cache, l2) = tiered(60);
+        l2.insert_item("a".into(), "1".into(), 60).await.unwrap();
+        let l2_hits = TIER_HIT_COUNTER.with_label_values(&["l2"]).get();
+
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        assert!(TIER_HIT_COUNTER.with_label_values(&["l2"]).get() > l2_hits);
+        assert_eq!(
+            cache
+                .l1
+                .retrieve_item("a")
+                .await
+                .unwrap()
+                .map(|copy| copy.value),
+            Some("1".to_string())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_l1_copies_are_capped() {
+        let (cache, l2) = tiered(5);
+        cache
+            .insert_item("a".into(), "old".into(), 60)
+            .await
+            .unwrap();
+        l2.insert_item("a".into(), "new".into(), 60).await.unwrap();
+
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("old".to_string())
+        );
+        time::advance(Duration::from_secs(6)).await;
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("new".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_reads_fall_through_to_l2() {
+        let (cache, l2) = tiered(60);
+        let results = cache.insert_items(vec![("a".into(), "1".into(), 60)]).await;
+        assert!(results[0].is_ok());
+        l2.insert_item("b".into(), "2".into(), 60).await.unwrap();
+        let l1_hits = TIER_HIT_COUNTER.with_label_values(&["l1"]).get();
+
+        let keys: Vec<String> = ["a", "b", "c"].map(String::from).into();
+        assert_eq!(
+            cache.retrieve_items(&keys).await,
+            vec![
+                Ok(Some("1".to_string())),
+                Ok(Some("2".to_string())),
+                Ok(None)
+            ]
+        );
+        assert!(TIER_HIT_COUNTER.with_label_values(&["l1"]).get() > l1_hits);
+        assert_eq!(
+            cache
+                .l1
+                .retrieve_item("b")
+                .await
+                .unwrap()
+                .map(|copy| copy.value),
+            Some("2".to_string())
+        );
+
+        cache.remove_items(&keys).await;
+        assert_eq!(cache.l1.retrieve_item("a").await.unwrap(), None);
+        assert_eq!(l2.retrieve_item("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_writes_check_l2() {
+        let (cache, l2) = tiered(60);
+        let version = cache
+            .write_item(
+                "a".into(),
+                "1".into(),
+                Some(60),
+                Precondition::Absent,
+                &[],
+                false,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cache.retrieve_versioned("a").await.unwrap(),
+            Some(("1".to_string(), version))
+        );
+
+        l2.insert_item("a".into(), "2".into(), 60).await.unwrap();
+        let conflict = cache
+            .write_item(
+                "a".into(),
+                "3".into(),
+                Some(60),
+                Precondition::Version(version),
+                &[],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(conflict, None);
+        assert_eq!(cache.l1.retrieve_item("a").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("2".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_increment_refreshes_l1() {
+        let (cache, _) = tiered(60);
+        cache.insert_item("n".into(), "1".into(), 60).await.unwrap();
+
+        assert_eq!(cache.increment("n", 2, None).await.unwrap(), 3);
+        assert_eq!(
+            cache.retrieve_item("n").await.unwrap(),
+            Some("3".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_tag_reaches_both_tiers() {
+        let (cache, l2) = tiered(60);
+        let tags = vec!["user:1".to_string()];
+        cache
+            .write_item(
+                "view".into(),
+                "v".into(),
+                Some(60),
+                Precondition::Always,
+                &tags,
+                false,
+            )
+            .await
+            .unwrap();
+        cache
+            .insert_item("other".into(), "v".into(), 60)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.invalidate_tag("user:1").await.unwrap(), 1);
+        assert_eq!(cache.retrieve_item("view").await.unwrap(), None);
+        assert_eq!(l2.retrieve_item("view").await.unwrap(), None);
+        assert_eq!(
+            cache.retrieve_item("other").await.unwrap(),
+            Some("v".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_expiry_applies_to_l2() {
+        let (cache, l2) = tiered(60);
+        cache.insert_item("a".into(), "1".into(), 60).await.unwrap();
+
+        assert!(cache.set_expiry("a", Expiry::Never).await.unwrap());
+        assert_eq!(cache.l1.retrieve_item("a").await.unwrap(), None);
+        assert_eq!(l2.retrieve_ttl("a").await.unwrap(), Some(None));
+        assert_eq!(cache.retrieve_ttl("a").await.unwrap(), Some(None));
+        assert!(!cache.set_expiry("b", Expiry::In(5)).await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_l1_misses_slide_l2() {
+        let (cache, l2) = tiered(1);
+        cache
+            .write_item(
+                "a".into(),
+                "1".into(),
+                Some(10),
+                Precondition::Always,
+                &[],
+                true,
+            )
+            .await
+            .unwrap();
+
+        time::advance(Duration::from_secs(8)).await;
+        assert_eq!(
+            cache.retrieve_item("a").await.unwrap(),
+            Some("1".to_string())
+        );
+        time::advance(Duration::from_secs(8)).await;
+        assert_eq!(l2.retrieve_item("a").await.unwrap(), Some("1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_miss_in_both_tiers() {
+        let (cache, _) = tiered(60);
+        let l2_misses = TIER_MISS_COUNTER.with_label_values(&["l2"]).get();
+
+        assert_eq!(cache.retrieve_item("missing").await.unwrap(), None);
+        assert!(TIER_MISS_COUNTER.with_label_values(&["l2"]).get() > l2_misses);
+    }
+}
@@ -0,0 +1,214 @@
+use super::compression::Codec;
+use super::in_memory_cache::Weigh;
+use super::now_millis;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use twox_hash::XxHash3_64;
+
+/// A value as the HTTP API stores it: raw bytes and the `Content-Type` they
+/// were written with, if any.
+///
+/// Bare text serializes as a plain JSON string, the form every value had
+/// before binary values existed, so older entries, snapshots and counters
+/// read back unchanged. Anything else serializes as an object of the
+/// metadata it has, with the data under `text` if it is uncompressed UTF-8
+/// and under `base64` otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheValue {
+    pub data: Bytes,
+    pub content_type: Option<String>,
+    /// How `data` is compressed; `None` for the raw bytes. Only
+    /// `CompressedCache` sets it, and it decompresses values before
+    /// returning them.
+    pub codec: Option<Codec>,
+    /// When the value was written, in Unix milliseconds.
+    pub written_at: Option<u64>,
+    /// xxh3 hash of the uncompressed data.
+    pub hash: Option<u64>,
+}
+
+impl CacheValue {
+    pub fn new(data: impl Into<Bytes>, content_type: Option<String>) -> Self {
+        Self {
+            data: data.into(),
+            content_type,
+            codec: None,
+            written_at: None,
+            hash: None,
+        }
+    }
+
+    /// Records the write time and content hash that conditional reads
+    /// answer from. The HTTP API stamps every value it writes; counters and
+    /// older entries have neither.
+    pub fn stamped(self) -> Self {
+        Self {
+            written_at: Some(now_millis()),
+            hash: Some(XxHash3_64::oneshot(&self.data)),
+            ..self
+        }
+    }
+
+    /// The data as text, if it is uncompressed and valid UTF-8.
+    pub fn as_text(&self) -> Option<&str> {
+        match self.codec {
+            None => std::str::from_utf8(&self.data).ok(),
+            Some(_) => None,
+        }
+    }
+}
+
+impl From<String> for CacheValue {
+    fn from(text: String) -> Self {
+        Self::new(text, None)
+    }
+}
+
+impl Weigh for CacheValue {
+    fn weigh(&self) -> usize {
+        self.data.len() + self.content_type.as_ref().map_or(0, String::len)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Text(String),
+    Object {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        codec: Option<Codec>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        written_at: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hash: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        base64: Option<String>,
+    },
+}
+
+impl Serialize for CacheValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bare = self.content_type.is_none() && self.written_at.is_none() && self.hash.is_none();
+        match self.as_text() {
+            Some(text) if bare => serializer.serialize_str(text),
+            text => Stored::Object {
+                content_type: self.content_type.clone(),
+                codec: self.codec,
+                written_at: self.written_at,
+                hash: self.hash,
+                text: text.map(str::to_string),
+                base64: text.is_none().then(|| STANDARD.encode(&self.data)),
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CacheValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Text(text) => Self::from(text),
+            Stored::Object {
+                content_type,
+                codec,
+                written_at,
+                hash,
+                text,
+                base64,
+            } => {
+                let data = match (text, base64) {
+                    (Some(text), None) => Bytes::from(text),
+                    (None, Some(base64)) => {
+                        STANDARD.decode(base64).map_err(de::Error::custom)?.into()
+                    }
+                    _ => return Err(de::Error::custom("expected exactly one of text and base64")),
+                };
+                Self {
+                    data,
+                    content_type,
+                    codec,
+                    written_at,
+                    hash,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::counter;
+
+    fn round_trip(value: &CacheValue) -> CacheValue {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_plain_text_is_a_json_string() {
+        let value = CacheValue::from("hello".to_string());
+        assert_eq!(serde_json::to_string(&value).unwrap(), "\"hello\"");
+        assert_eq!(round_trip(&value), value);
+        let legacy: CacheValue = serde_json::from_str("\"stored before\"").unwrap();
+        assert_eq!(legacy, CacheValue::from("stored before".to_string()));
+    }
+
+    #[test]
+    fn test_binary_and_typed_values_round_trip() {
+        let blob = CacheValue::new(vec![0u8, 159, 146, 150, 255], None);
+        assert_eq!(round_trip(&blob), blob);
+
+        let typed = CacheValue::new("{}", Some("application/json".to_string()));
+        assert_eq!(
+            serde_json::to_string(&typed).unwrap(),
+            r#"{"content_type":"application/json","text":"{}"}"#
+        );
+        assert_eq!(round_trip(&typed), typed);
+
+        let compressed = CacheValue {
+            codec: Some(Codec::Lz4),
+            ..CacheValue::from("text".to_string())
+        };
+        assert_eq!(
+            serde_json::to_string(&compressed).unwrap(),
+            r#"{"codec":"lz4","base64":"dGV4dA=="}"#
+        );
+        assert_eq!(round_trip(&compressed), compressed);
+    }
+
+    #[test]
+    fn test_stamped_values_keep_their_metadata() {
+        let stamped = CacheValue::from("42".to_string()).stamped();
+        assert!(stamped.written_at.is_some());
+        assert_eq!(stamped.hash, Some(XxHash3_64::oneshot(b"42")));
+        assert_eq!(round_trip(&stamped), stamped);
+        assert_eq!(counter::decode(&stamped), Some(42));
+
+        let typed = CacheValue {
+            content_type: Some("text/plain".to_string()),
+            ..stamped
+        };
+        let incremented = counter::replace(&typed, 43).unwrap();
+        assert_eq!(incremented.as_text(), Some("43"));
+        assert_eq!(incremented.content_type.as_deref(), Some("text/plain"));
+        assert!(incremented.written_at >= typed.written_at);
+        assert_eq!(incremented.hash, None);
+
+        assert!(serde_json::from_str::<CacheValue>(r#"{"hash":1}"#).is_err());
+    }
+
+    #[test]
+    fn test_counters_stay_text() {
+        let value: CacheValue = counter::encode(42).unwrap();
+        assert_eq!(value, CacheValue::from("42".to_string()));
+        assert_eq!(counter::decode(&value), Some(42));
+    }
+}
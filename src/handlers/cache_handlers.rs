@@ -1,17 +1,485 @@
-use crate::cache::Cache;
-use actix_web::{web, HttpResponse, Responder};
+use crate::cache::{
+    self, Cache, CacheError, CacheValue, CounterError, Expiry, KeyPattern, OriginError,
+    OriginLoader, Precondition, MAX_TTL_SECS, RESERVED_KEY_PREFIX,
+};
+use actix_web::http::header::{self, EntityTag, Header, HeaderMap};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use prometheus::{Counter, Opts};
 use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct CacheItem {
     key: String,
     data: String,
+    /// TTL in seconds; the item never expires if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u64>,
+    /// Tags to invalidate the item by, through `DELETE /cache/tags/{tag}`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Restart the TTL on every read, so the item only expires once idle.
+    #[serde(default)]
+    sliding: bool,
+}
+
+/// Most tags a single item may carry.
+const MAX_TAGS: usize = 32;
+
+/// Tags must be non-empty and free of `,` and control characters, which the
+/// Redis backend uses to frame them next to the value.
+fn check_tags(tags: &[String]) -> Result<(), String> {
+    if tags.len() > MAX_TAGS {
+        return Err(format!("an item may carry at most {} tags", MAX_TAGS));
+    }
+    match tags
+        .iter()
+        .find(|tag| tag.is_empty() || tag.contains(|c: char| c == ',' || c.is_control()))
+    {
+        Some(tag) => Err(format!(
+            "invalid tag {:?}: tags must be non-empty and contain no ',' or control characters",
+            tag
+        )),
+        None => Ok(()),
+    }
+}
+
+fn check_ttl(ttl: Option<u64>, sliding: bool, limits: &TtlLimits) -> Result<(), ErrorBody> {
+    if sliding && ttl.is_none() {
+        return Err(ErrorBody::new(
+            "invalid_ttl",
+            "sliding expiration requires a ttl".to_string(),
+        ));
+    }
+    limits.check(ttl)
+}
+
+/// Query of `PUT /cache/{key}`, whose body is the value itself.
+#[derive(Deserialize)]
+pub struct RawWrite {
+    /// TTL in seconds; the item never expires if omitted.
+    ttl: Option<u64>,
+    /// Comma-separated tags; empty ones, as in `?tags=` or `?tags=a,`, are
+    /// dropped.
+    tags: Option<String>,
+    #[serde(default)]
+    sliding: bool,
+}
+
+/// JSON body of structured 400 responses: a stable machine-readable code and
+/// a human-readable message.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct ErrorBody {
+    /// One of `invalid_ttl`, `ttl_out_of_range` or `ttl_required`.
+    error: String,
+    message: String,
+}
+
+impl ErrorBody {
+    fn new(error: &str, message: String) -> Self {
+        Self {
+            error: error.to_string(),
+            message,
+        }
+    }
+}
+
+impl From<ErrorBody> for HttpResponse {
+    fn from(body: ErrorBody) -> Self {
+        HttpResponse::BadRequest().json(body)
+    }
+}
+
+/// Server-side bounds on the TTLs clients may ask for, in seconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TtlLimits {
+    pub min: Option<u64>,
+    /// When set, every write must carry a TTL. TTLs above `MAX_TTL_SECS` are
+    /// rejected regardless.
+    pub max: Option<u64>,
+}
+
+impl TtlLimits {
+    /// Reads `CACHE_MIN_TTL_SECS` and `CACHE_MAX_TTL_SECS`; unset or `0`
+    /// means no bound but `MAX_TTL_SECS`.
+    pub fn from_env() -> Self {
+        let bound = |name: &str| {
+            env::var(name)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse()
+                        .unwrap_or_else(|_| panic!("{} must be a non-negative integer", name))
+                })
+                .filter(|&secs| secs > 0)
+        };
+        let limits = Self {
+            min: bound("CACHE_MIN_TTL_SECS"),
+            max: bound("CACHE_MAX_TTL_SECS"),
+        };
+        assert!(
+            limits.max.unwrap_or(0) <= MAX_TTL_SECS,
+            "CACHE_MAX_TTL_SECS must not exceed {}",
+            MAX_TTL_SECS
+        );
+        if let (Some(min), Some(max)) = (limits.min, limits.max) {
+            assert!(
+                min <= max,
+                "CACHE_MIN_TTL_SECS must not exceed CACHE_MAX_TTL_SECS"
+            );
+        }
+        limits
+    }
+
+    /// Checks a requested TTL; `None` asks for no expiry.
+    fn check(&self, ttl: Option<u64>) -> Result<(), ErrorBody> {
+        match ttl {
+            Some(0) => Err(ErrorBody::new(
+                "invalid_ttl",
+                "ttl must be at least 1 second; omit it for no expiry".to_string(),
+            )),
+            None => match self.max {
+                Some(max) => Err(ErrorBody::new(
+                    "ttl_required",
+                    format!("a ttl of at most {} seconds is required", max),
+                )),
+                None => Ok(()),
+            },
+            Some(ttl)
+                if self.min.is_some_and(|min| ttl < min)
+                    || ttl > self.max.unwrap_or(MAX_TTL_SECS) =>
+            {
+                Err(ErrorBody::new(
+                    "ttl_out_of_range",
+                    format!(
+                        "ttl of {} seconds is outside the allowed range of {} to {} seconds",
+                        ttl,
+                        self.min.unwrap_or(1),
+                        self.max.unwrap_or(MAX_TTL_SECS)
+                    ),
+                ))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// Body of the increment and decrement endpoints.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct CounterDelta {
+    /// Amount to add or subtract; 1 if omitted.
+    #[serde(default = "CounterDelta::default_delta")]
+    delta: i64,
+    /// TTL in seconds if the counter is created; no expiry if omitted.
+    ttl: Option<u64>,
+}
+
+impl CounterDelta {
+    fn default_delta() -> i64 {
+        1
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct CounterValue {
+    value: i64,
+}
+
+/// Remaining TTL of an item in seconds; `null` if it never expires.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct TtlValue {
+    ttl: Option<u64>,
+}
+
+/// Body of the touch endpoint.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct TouchRequest {
+    /// New TTL in seconds, counted from now.
     ttl: u64,
 }
 
+/// Body of the expire-at endpoint.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct ExpireAtRequest {
+    /// Unix time in seconds; a time already past removes the item.
+    at: u64,
+}
+
+/// Keys per page of `GET /cache` when no limit is given, and the largest
+/// limit accepted.
+const DEFAULT_SCAN_LIMIT: usize = 100;
+const MAX_SCAN_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct KeyScan {
+    #[serde(default)]
+    prefix: String,
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+/// A listed key with its remaining TTL in seconds; no TTL if it never expires.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct KeyEntry {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct KeyList {
+    keys: Vec<KeyEntry>,
+    /// Pass as `cursor` to fetch the next page; absent once the scan is done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct KeySelector {
+    prefix: Option<String>,
+    pattern: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct RemovedCount {
+    removed: u64,
+}
+
+/// Most keys a single batch request may name.
+const MAX_BATCH_SIZE: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct BatchKeys {
+    keys: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct BatchItems {
+    items: Vec<CacheItem>,
+}
+
+/// Outcome for one key of a batch, with the status code the single-key
+/// endpoint would have answered.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct BatchResult {
+    key: String,
+    status: u16,
+    /// The value, if it is valid UTF-8.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    /// The value in base64, if it is not valid UTF-8.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_base64: Option<String>,
+    /// The `Content-Type` the value was stored with, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BatchResult {
+    fn write(key: String, result: cache::CacheResult<()>) -> Self {
+        match result {
+            Ok(()) => Self::new(key, StatusCode::OK, None, None),
+            Err(e) => Self::failed(key, e, "write"),
+        }
+    }
+
+    fn read(key: String, result: cache::CacheResult<Option<CacheValue>>) -> Self {
+        match result {
+            Ok(Some(value)) => {
+                let mut found = Self::new(key, StatusCode::OK, None, None);
+                match value.as_text() {
+                    Some(text) => found.data = Some(text.to_string()),
+                    None => found.data_base64 = Some(STANDARD.encode(&value.data)),
+                }
+                found.content_type = value.content_type;
+                found
+            }
+            Ok(None) => Self::new(key, StatusCode::NOT_FOUND, None, None),
+            Err(e) => Self::failed(key, e, "read"),
+        }
+    }
+
+    fn failed(key: String, e: CacheError, action: &str) -> Self {
+        let status = error_status(&e);
+        let error = if status == StatusCode::INTERNAL_SERVER_ERROR {
+            log::error!("Failed to {} {}: {}", action, key, e);
+            None
+        } else {
+            Some(e.to_string())
+        };
+        Self::new(key, status, None, error)
+    }
+
+    fn new(key: String, status: StatusCode, data: Option<String>, error: Option<String>) -> Self {
+        Self {
+            key,
+            status: status.as_u16(),
+            data,
+            data_base64: None,
+            content_type: None,
+            error,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+/// Status code the API answers a failed cache operation with.
+fn error_status(e: &CacheError) -> StatusCode {
+    match e {
+        CacheError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+        CacheError::Counter(_) => StatusCode::CONFLICT,
+        CacheError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        CacheError::NotAdmitted(_) => StatusCode::INSUFFICIENT_STORAGE,
+        CacheError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        CacheError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        CacheError::Serialization(_) | CacheError::Backend(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Response for a failed cache operation. Internal errors are logged with
+/// `context` and get an empty body; the others carry the error message.
+fn error_response(e: CacheError, context: fmt::Arguments) -> HttpResponse {
+    let status = error_status(&e);
+    match status {
+        StatusCode::INTERNAL_SERVER_ERROR => {
+            log::error!("{}: {}", context, e);
+            HttpResponse::InternalServerError().finish()
+        }
+        StatusCode::SERVICE_UNAVAILABLE => {
+            log::warn!("{}: {}", context, e);
+            HttpResponse::ServiceUnavailable().body(e.to_string())
+        }
+        _ => HttpResponse::build(status).body(e.to_string()),
+    }
+}
+
+/// Rejects keys in the namespace the backends keep for themselves.
+fn reserved_key(key: &str) -> Option<HttpResponse> {
+    key.starts_with(RESERVED_KEY_PREFIX).then(|| {
+        HttpResponse::BadRequest().body(format!(
+            "invalid key {:?}: keys starting with {:?} are reserved",
+            key, RESERVED_KEY_PREFIX
+        ))
+    })
+}
+
+fn batch_too_large(size: usize) -> Option<HttpResponse> {
+    (size > MAX_BATCH_SIZE).then(|| {
+        HttpResponse::BadRequest().body(format!(
+            "batch of {} keys exceeds the limit of {}",
+            size, MAX_BATCH_SIZE
+        ))
+    })
+}
+
+/// Reads the write precondition from `If-Match` or `If-None-Match`. Only `*`
+/// and a single strong ETag are understood.
+fn precondition(headers: &HeaderMap) -> Result<Precondition, String> {
+    let value = |name| {
+        headers
+            .get(name)
+            .map(|value| value.to_str().map(str::trim).map_err(|e| e.to_string()))
+            .transpose()
+    };
+    match (value(header::IF_MATCH)?, value(header::IF_NONE_MATCH)?) {
+        (None, None) => Ok(Precondition::Always),
+        (Some(_), Some(_)) => Err("If-Match and If-None-Match cannot be combined".to_string()),
+        (None, Some("*")) => Ok(Precondition::Absent),
+        (None, Some(tag)) => Err(format!("unsupported If-None-Match: {}", tag)),
+        (Some("*"), None) => Ok(Precondition::Present),
+        (Some(tag), None) => tag
+            .strip_prefix('"')
+            .and_then(|tag| tag.strip_suffix('"'))
+            .and_then(|version| version.parse().ok())
+            .map(Precondition::Version)
+            .ok_or_else(|| format!("unsupported If-Match: {}", tag)),
+    }
+}
+
+/// Adds the ETag for `version`; unversioned entries (version 0) get none.
+fn with_etag(mut response: HttpResponseBuilder, version: u64) -> HttpResponseBuilder {
+    if version > 0 {
+        response.insert_header(header::ETag(header::EntityTag::new_strong(
+            version.to_string(),
+        )));
+    }
+    response
+}
+
+/// The ETag of a read: the entry's version where the backend keeps one, so
+/// it can be sent back in `If-Match`, and the content hash otherwise.
+fn read_etag(value: &CacheValue, version: u64) -> Option<EntityTag> {
+    if version > 0 {
+        Some(EntityTag::new_strong(version.to_string()))
+    } else {
+        value
+            .hash
+            .map(|hash| EntityTag::new_strong(format!("{:016x}", hash)))
+    }
+}
+
+/// Adds the headers clients validate and expire their copy by: `ETag`, and
+/// where known `Last-Modified`, `Age` and a `Cache-Control: max-age` of the
+/// remaining TTL.
+fn with_validators(
+    mut response: HttpResponseBuilder,
+    etag: Option<&EntityTag>,
+    value: &CacheValue,
+    ttl: Option<u64>,
+) -> HttpResponseBuilder {
+    if let Some(etag) = etag {
+        response.insert_header(header::ETag(etag.clone()));
+    }
+    if let Some(written_at) = value.written_at {
+        let written = UNIX_EPOCH + Duration::from_millis(written_at);
+        response.insert_header(header::LastModified(written.into()));
+        response.insert_header((
+            header::AGE,
+            cache::now_millis().saturating_sub(written_at) / 1000,
+        ));
+    }
+    if let Some(ttl) = ttl {
+        response.insert_header(header::CacheControl(vec![header::CacheDirective::MaxAge(
+            u32::try_from(ttl).unwrap_or(u32::MAX),
+        )]));
+    }
+    response
+}
+
+/// Whether the client's copy is current, so a GET can answer 304.
+/// `If-Modified-Since` is only consulted without `If-None-Match`, and an
+/// unparsable condition never matches.
+fn not_modified(req: &HttpRequest, etag: Option<&EntityTag>, written_at: Option<u64>) -> bool {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return match (header::IfNoneMatch::parse(req), etag) {
+            (Ok(header::IfNoneMatch::Any), _) => true,
+            (Ok(header::IfNoneMatch::Items(tags)), Some(etag)) => {
+                tags.iter().any(|tag| tag.weak_eq(etag))
+            }
+            _ => false,
+        };
+    }
+    match (header::IfModifiedSince::parse(req), written_at) {
+        (Ok(header::IfModifiedSince(since)), Some(written_at)) => SystemTime::from(since)
+            .duration_since(UNIX_EPOCH)
+            .is_ok_and(|since| written_at / 1000 <= since.as_secs()),
+        _ => false,
+    }
+}
+
 lazy_static::lazy_static! {
     static ref REQUEST_COUNTER: Counter = Counter::with_opts(Opts::new("requests", "Number of requests")).unwrap();
     static ref WRITE_COUNTER: Counter = Counter::with_opts(Opts::new("writes", "Number of write requests")).unwrap();
@@ -24,53 +492,337 @@ pub fn init_metrics(registry: &prometheus::Registry) {
         .unwrap();
     registry.register(Box::new(WRITE_COUNTER.clone())).unwrap();
     registry.register(Box::new(READ_COUNTER.clone())).unwrap();
+    cache::metrics::register(registry);
 }
 
 #[utoipa::path(
     post,
     path = "/cache",
     request_body = CacheItem,
+    params(
+        ("If-Match" = Option<String>, Header, description = "Only write if the item exists (`*`) or has this ETag"),
+        ("If-None-Match" = Option<String>, Header, description = "`*`: only write if the item does not exist")
+    ),
     responses(
-    (status = 200, description = "Cache item created"),
-    (status = 500, description = "Internal server error")
+    (status = 200, description = "Cache item created; the ETag header carries its version"),
+    (status = 400, description = "Reserved key, unsupported precondition header, invalid tags, or a TTL rejected with an error code", body = ErrorBody),
+    (status = 412, description = "Precondition failed"),
+    (status = 413, description = "Cache item exceeds the memory budget"),
+    (status = 507, description = "The cache evicted the new item at once to stay within its limits"),
+    (status = 500, description = "Internal server error"),
+    (status = 501, description = "The backend does not support conditional writes, tags or sliding expiration"),
+    (status = 503, description = "The backend is unavailable")
     )
 )]
 pub async fn create_item(
-    cache: web::Data<Arc<dyn Cache<String>>>,
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    req: HttpRequest,
     item: web::Json<CacheItem>,
 ) -> impl Responder {
     REQUEST_COUNTER.inc();
     WRITE_COUNTER.inc();
+    let item = item.into_inner();
+    write_value(
+        &cache,
+        &limits,
+        &req,
+        item.key,
+        CacheValue::from(item.data),
+        item.ttl,
+        &item.tags,
+        item.sliding,
+    )
+    .await
+}
+
+#[utoipa::path(
+    put,
+    path = "/cache/{key}",
+    request_body(content = Vec<u8>, description = "The value, stored byte for byte with its Content-Type", content_type = "*/*"),
+    params(
+        ("key" = String, Path, description = "Key to write"),
+        ("ttl" = Option<u64>, Query, description = "TTL in seconds; the item never expires if omitted"),
+        ("tags" = Option<String>, Query, description = "Comma-separated tags to invalidate the item by"),
+        ("sliding" = Option<bool>, Query, description = "Restart the TTL on every read"),
+        ("If-Match" = Option<String>, Header, description = "Only write if the item exists (`*`) or has this ETag"),
+        ("If-None-Match" = Option<String>, Header, description = "`*`: only write if the item does not exist")
+    ),
+    responses(
+    (status = 200, description = "Cache item written; the ETag header carries its version"),
+    (status = 400, description = "Reserved key, unsupported precondition header, invalid Content-Type or tags, or a TTL rejected with an error code", body = ErrorBody),
+    (status = 412, description = "Precondition failed"),
+    (status = 413, description = "Body exceeds the payload limit or the memory budget"),
+    (status = 507, description = "The cache evicted the new item at once to stay within its limits"),
+    (status = 500, description = "Internal server error"),
+    (status = 501, description = "The backend does not support conditional writes, tags or sliding expiration"),
+    (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn put_item(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    req: HttpRequest,
+    key: web::Path<String>,
+    query: web::Query<RawWrite>,
+    body: web::Bytes,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    WRITE_COUNTER.inc();
+    let content_type = match req.headers().get(header::CONTENT_TYPE) {
+        Some(value) => match value.to_str() {
+            Ok(value) => Some(value.to_string()),
+            Err(_) => return HttpResponse::BadRequest().body("invalid Content-Type header"),
+        },
+        None => None,
+    };
+    let query = query.into_inner();
+    let tags: Vec<String> = query
+        .tags
+        .map(|tags| {
+            tags.split(',')
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    write_value(
+        &cache,
+        &limits,
+        &req,
+        key.into_inner(),
+        CacheValue::new(body, content_type),
+        query.ttl,
+        &tags,
+        query.sliding,
+    )
+    .await
+}
+
+/// Shared by `create_item` and `put_item`, which only differ in how the
+/// value arrives.
+#[allow(clippy::too_many_arguments)]
+async fn write_value(
+    cache: &Arc<dyn Cache<CacheValue>>,
+    limits: &TtlLimits,
+    req: &HttpRequest,
+    key: String,
+    value: CacheValue,
+    ttl: Option<u64>,
+    tags: &[String],
+    sliding: bool,
+) -> HttpResponse {
+    if let Some(response) = reserved_key(&key) {
+        return response;
+    }
+    let condition = match precondition(req.headers()) {
+        Ok(condition) => condition,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    if let Err(e) = check_tags(tags) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if let Err(e) = check_ttl(ttl, sliding, limits) {
+        return e.into();
+    }
     match cache
-        .insert_item(item.key.clone(), item.data.clone(), item.ttl)
+        .write_item(key, value.stamped(), ttl, condition, tags, sliding)
         .await
     {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(e) => {
-            log::error!("Failed to insert item: {}", e);
-            HttpResponse::InternalServerError().finish()
+        Ok(Some(version)) => with_etag(HttpResponse::Ok(), version).finish(),
+        Ok(None) => HttpResponse::PreconditionFailed().finish(),
+        Err(e) => error_response(e, format_args!("Failed to insert item")),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/cache/tags/{tag}",
+    params(
+        ("tag" = String, Path, description = "Tag whose items to remove")
+    ),
+    responses(
+        (status = 200, description = "Number of items removed", body = RemovedCount),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support tags"),
+        (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn invalidate_tag(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    tag: web::Path<String>,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    WRITE_COUNTER.inc();
+    match cache.invalidate_tag(&tag).await {
+        Ok(removed) => HttpResponse::Ok().json(RemovedCount { removed }),
+        Err(e) => error_response(e, format_args!("Failed to invalidate tag {}", tag)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/cache",
+    params(
+        ("prefix" = Option<String>, Query, description = "Only list keys starting with this prefix"),
+        ("cursor" = Option<String>, Query, description = "Cursor returned by the previous page"),
+        ("limit" = Option<usize>, Query, description = "Keys per page, 1 to 1000; defaults to 100")
+    ),
+    responses(
+        (status = 200, description = "One page of keys with their remaining TTLs", body = KeyList),
+        (status = 400, description = "Invalid cursor or limit"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support key listing"),
+        (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn list_keys(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    query: web::Query<KeyScan>,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    READ_COUNTER.inc();
+    let limit = query.limit.unwrap_or(DEFAULT_SCAN_LIMIT);
+    if !(1..=MAX_SCAN_LIMIT).contains(&limit) {
+        return HttpResponse::BadRequest()
+            .body(format!("limit must be between 1 and {}", MAX_SCAN_LIMIT));
+    }
+    match cache
+        .scan_keys(&query.prefix, query.cursor.as_deref(), limit)
+        .await
+    {
+        Ok(page) => HttpResponse::Ok().json(KeyList {
+            keys: page
+                .keys
+                .into_iter()
+                .map(|info| KeyEntry {
+                    key: info.key,
+                    ttl: info.ttl,
+                })
+                .collect(),
+            cursor: page.cursor,
+        }),
+        Err(e) => error_response(e, format_args!("Failed to list keys")),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/cache",
+    params(
+        ("prefix" = Option<String>, Query, description = "Remove every key starting with this prefix"),
+        ("pattern" = Option<String>, Query, description = "Remove every key matching this Redis glob pattern")
+    ),
+    responses(
+        (status = 200, description = "Number of keys removed", body = RemovedCount),
+        (status = 400, description = "Neither or both of prefix and pattern given, or an empty one"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support bulk removal"),
+        (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn remove_matching(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    query: web::Query<KeySelector>,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    WRITE_COUNTER.inc();
+    let pattern = match query.into_inner() {
+        KeySelector {
+            prefix: Some(prefix),
+            pattern: None,
+        } if !prefix.is_empty() => KeyPattern::Prefix(prefix),
+        KeySelector {
+            prefix: None,
+            pattern: Some(pattern),
+        } if !pattern.is_empty() => KeyPattern::Glob(pattern),
+        _ => {
+            return HttpResponse::BadRequest()
+                .body("exactly one non-empty prefix or pattern is required")
         }
+    };
+    match cache.remove_matching(&pattern).await {
+        Ok(removed) => HttpResponse::Ok().json(RemovedCount { removed }),
+        Err(e) => error_response(
+            e,
+            format_args!("Failed to remove keys matching {:?}", pattern),
+        ),
     }
 }
 
 #[utoipa::path(
     get,
     path = "/cache/{key}",
+    params(
+        ("If-None-Match" = Option<String>, Header, description = "Answer 304 if the item still has one of these ETags, or exists at all (`*`)"),
+        ("If-Modified-Since" = Option<String>, Header, description = "Answer 304 if the item was not written since; ignored with If-None-Match")
+    ),
     responses(
-        (status = 200, description = "Cache item retrieved"),
-        (status = 404, description = "Cache item not found"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Cache item retrieved byte for byte with the Content-Type it was stored with, and with ETag, Last-Modified, Age and Cache-Control headers"),
+        (status = 304, description = "The client's copy is current"),
+        (status = 400, description = "Reserved key"),
+        (status = 404, description = "Cache item not found, and not found at the origin of its namespace if it has one"),
+        (status = 500, description = "Internal server error"),
+        (status = 502, description = "The origin of the key's namespace failed"),
+        (status = 503, description = "The backend is unavailable"),
+        (status = 504, description = "The origin of the key's namespace timed out")
     )
 )]
 pub async fn retrieve_item(
-    cache: web::Data<Arc<dyn Cache<String>>>,
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    origins: web::Data<OriginLoader>,
+    req: HttpRequest,
     key: web::Path<String>,
 ) -> impl Responder {
     REQUEST_COUNTER.inc();
     READ_COUNTER.inc();
-    match cache.retrieve_item(&key.into_inner()).await {
-        Some(data) => HttpResponse::Ok().body(data),
-        None => HttpResponse::NotFound().finish(),
+    if let Some(response) = reserved_key(&key) {
+        return response;
+    }
+    match cache.retrieve_entry(&key).await {
+        Ok(Some(entry)) => value_response(&req, entry.value, entry.version, entry.ttl),
+        Ok(None) => load_from_origin(&cache, &origins, &req, &key).await,
+        Err(e) => error_response(e, format_args!("Failed to read {}", key)),
+    }
+}
+
+/// Answers a read of `value`: 304 if the client's copy is current, and
+/// otherwise the bytes with their Content-Type and validators.
+fn value_response(
+    req: &HttpRequest,
+    value: CacheValue,
+    version: u64,
+    ttl: Option<u64>,
+) -> HttpResponse {
+    let etag = read_etag(&value, version);
+    if not_modified(req, etag.as_ref(), value.written_at) {
+        return with_validators(HttpResponse::NotModified(), etag.as_ref(), &value, ttl).finish();
+    }
+    let mut response = with_validators(HttpResponse::Ok(), etag.as_ref(), &value, ttl);
+    if let Some(content_type) = value.content_type {
+        response.insert_header((header::CONTENT_TYPE, content_type));
+    }
+    response.body(value.data)
+}
+
+/// Read-through on a miss: loads `key` from the origin of its namespace,
+/// if it has one.
+async fn load_from_origin(
+    cache: &Arc<dyn Cache<CacheValue>>,
+    origins: &OriginLoader,
+    req: &HttpRequest,
+    key: &str,
+) -> HttpResponse {
+    match origins.load(cache, key).await {
+        Ok(Some(loaded)) => value_response(req, loaded.value, loaded.version, loaded.ttl),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            log::warn!("Failed to load {}: {}", key, e);
+            match e {
+                OriginError::Timeout(_) => HttpResponse::GatewayTimeout().body("origin timed out"),
+                OriginError::Upstream(_) => HttpResponse::BadGateway().body("origin failed"),
+            }
+        }
     }
 }
 
@@ -79,21 +831,365 @@ pub async fn retrieve_item(
     path = "/cache/{key}",
     responses(
         (status = 200, description = "Cache item deleted"),
+        (status = 400, description = "Reserved key"),
         (status = 404, description = "Cache item not found"),
-        (status = 500, description = "Internal server error")
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "The backend is unavailable")
     )
 )]
 pub async fn remove_item(
-    cache: web::Data<Arc<dyn Cache<String>>>,
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
     key: web::Path<String>,
 ) -> impl Responder {
     REQUEST_COUNTER.inc();
     WRITE_COUNTER.inc();
-    match cache.remove_item(&key.into_inner()).await {
+    if let Some(response) = reserved_key(&key) {
+        return response;
+    }
+    match cache.remove_item(&key).await {
         Ok(_) => HttpResponse::Ok().finish(),
-        Err(e) => {
-            log::error!("Failed to remove item: {}", e);
-            HttpResponse::InternalServerError().finish()
+        Err(e) => error_response(e, format_args!("Failed to remove {}", key)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/{key}/incr",
+    request_body = CounterDelta,
+    responses(
+        (status = 200, description = "Counter incremented", body = CounterValue),
+        (status = 400, description = "Reserved key, or TTL rejected", body = ErrorBody),
+        (status = 409, description = "The value is not an integer, or the result overflows"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support counters"),
+        (status = 503, description = "The backend is unavailable"),
+        (status = 507, description = "The cache evicted the new counter at once to stay within its limits")
+    )
+)]
+pub async fn increment_item(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    key: web::Path<String>,
+    body: web::Json<CounterDelta>,
+) -> impl Responder {
+    add_to_counter(cache, &limits, key.into_inner(), Some(body.delta), body.ttl).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/{key}/decr",
+    request_body = CounterDelta,
+    responses(
+        (status = 200, description = "Counter decremented", body = CounterValue),
+        (status = 400, description = "Reserved key, or TTL rejected", body = ErrorBody),
+        (status = 409, description = "The value is not an integer, or the result overflows"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support counters"),
+        (status = 503, description = "The backend is unavailable"),
+        (status = 507, description = "The cache evicted the new counter at once to stay within its limits")
+    )
+)]
+pub async fn decrement_item(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    key: web::Path<String>,
+    body: web::Json<CounterDelta>,
+) -> impl Responder {
+    add_to_counter(
+        cache,
+        &limits,
+        key.into_inner(),
+        body.delta.checked_neg(),
+        body.ttl,
+    )
+    .await
+}
+
+/// Shared by increment and decrement; `delta` is `None` if negating it
+/// overflowed.
+async fn add_to_counter(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: &TtlLimits,
+    key: String,
+    delta: Option<i64>,
+    ttl: Option<u64>,
+) -> HttpResponse {
+    REQUEST_COUNTER.inc();
+    WRITE_COUNTER.inc();
+    if let Some(response) = reserved_key(&key) {
+        return response;
+    }
+    if let Err(e) = limits.check(ttl) {
+        return e.into();
+    }
+    let Some(delta) = delta else {
+        return HttpResponse::Conflict().body(CounterError::Overflow.to_string());
+    };
+    match cache.increment(&key, delta, ttl).await {
+        Ok(value) => HttpResponse::Ok().json(CounterValue { value }),
+        Err(e) => error_response(e, format_args!("Failed to update counter {}", key)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/cache/{key}/ttl",
+    responses(
+        (status = 200, description = "Remaining TTL of the item", body = TtlValue),
+        (status = 400, description = "Reserved key"),
+        (status = 404, description = "Cache item not found"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support TTL management"),
+        (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn retrieve_ttl(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    key: web::Path<String>,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    READ_COUNTER.inc();
+    if let Some(response) = reserved_key(&key) {
+        return response;
+    }
+    match cache.retrieve_ttl(&key).await {
+        Ok(Some(ttl)) => HttpResponse::Ok().json(TtlValue { ttl }),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => error_response(e, format_args!("Failed to read TTL of {}", key)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/{key}/touch",
+    request_body = TouchRequest,
+    responses(
+        (status = 200, description = "TTL replaced"),
+        (status = 400, description = "Reserved key, or TTL rejected", body = ErrorBody),
+        (status = 404, description = "Cache item not found"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support TTL management"),
+        (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn touch_item(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    key: web::Path<String>,
+    body: web::Json<TouchRequest>,
+) -> impl Responder {
+    let checked = limits.check(Some(body.ttl));
+    change_expiry(cache, key.into_inner(), Expiry::In(body.ttl), checked).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/{key}/persist",
+    responses(
+        (status = 200, description = "Expiry removed"),
+        (status = 400, description = "Reserved key, or a maximum TTL is configured", body = ErrorBody),
+        (status = 404, description = "Cache item not found"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support TTL management"),
+        (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn persist_item(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    key: web::Path<String>,
+) -> impl Responder {
+    let checked = limits.check(None);
+    change_expiry(cache, key.into_inner(), Expiry::Never, checked).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/{key}/expire-at",
+    request_body = ExpireAtRequest,
+    responses(
+        (status = 200, description = "Expiry set"),
+        (status = 400, description = "Reserved key, or expiry outside the allowed TTL range", body = ErrorBody),
+        (status = 404, description = "Cache item not found"),
+        (status = 500, description = "Internal server error"),
+        (status = 501, description = "The backend does not support TTL management"),
+        (status = 503, description = "The backend is unavailable")
+    )
+)]
+pub async fn expire_item_at(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    key: web::Path<String>,
+    body: web::Json<ExpireAtRequest>,
+) -> impl Responder {
+    let now = cache::now_millis() / 1000;
+    // A time already past removes the item, which no bound forbids.
+    let checked = match body.at.saturating_sub(now) {
+        0 => Ok(()),
+        ttl => limits.check(Some(ttl)),
+    };
+    change_expiry(cache, key.into_inner(), Expiry::At(body.at), checked).await
+}
+
+/// Shared by touch, persist and expire-at; `checked` is the outcome of
+/// checking the new expiry against the TTL limits.
+async fn change_expiry(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    key: String,
+    expiry: Expiry,
+    checked: Result<(), ErrorBody>,
+) -> HttpResponse {
+    REQUEST_COUNTER.inc();
+    WRITE_COUNTER.inc();
+    if let Some(response) = reserved_key(&key) {
+        return response;
+    }
+    if let Err(e) = checked {
+        return e.into();
+    }
+    match cache.set_expiry(&key, expiry).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => error_response(e, format_args!("Failed to change expiry of {}", key)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/batch/get",
+    request_body = BatchKeys,
+    responses(
+        (status = 200, description = "Per-key results: 200 with the data, 404, 500 or 503", body = BatchResponse),
+        (status = 400, description = "Too many keys in the batch, or a reserved key")
+    )
+)]
+pub async fn retrieve_items(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    batch: web::Json<BatchKeys>,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    READ_COUNTER.inc();
+    if let Some(response) = batch_too_large(batch.keys.len()) {
+        return response;
+    }
+    if let Some(response) = batch.keys.iter().find_map(|key| reserved_key(key)) {
+        return response;
+    }
+    let keys = batch.into_inner().keys;
+    let values = cache.retrieve_items(&keys).await;
+    let results = keys
+        .into_iter()
+        .zip(values)
+        .map(|(key, result)| BatchResult::read(key, result))
+        .collect();
+    HttpResponse::Ok().json(BatchResponse { results })
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/batch/set",
+    request_body = BatchItems,
+    responses(
+        (status = 200, description = "Per-key results: 200, 413, 500, 501, 503 or 507", body = BatchResponse),
+        (status = 400, description = "Too many items in the batch, a reserved key, invalid tags, or a TTL rejected with an error code", body = ErrorBody)
+    )
+)]
+pub async fn create_items(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    limits: web::Data<TtlLimits>,
+    batch: web::Json<BatchItems>,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    WRITE_COUNTER.inc();
+    if let Some(response) = batch_too_large(batch.items.len()) {
+        return response;
+    }
+    if let Some(response) = batch.items.iter().find_map(|item| reserved_key(&item.key)) {
+        return response;
+    }
+    if let Some(e) = batch
+        .items
+        .iter()
+        .find_map(|item| check_tags(&item.tags).err())
+    {
+        return HttpResponse::BadRequest().body(e);
+    }
+    for item in &batch.items {
+        if let Err(mut e) = check_ttl(item.ttl, item.sliding, &limits) {
+            e.message = format!("{}: {}", item.key, e.message);
+            return e.into();
         }
     }
+    // Batch inserts carry no tags, never slide and always expire, so such
+    // items are written one by one.
+    let (single, batched): (Vec<_>, Vec<_>) = batch
+        .into_inner()
+        .items
+        .into_iter()
+        .enumerate()
+        .partition(|(_, item)| !item.tags.is_empty() || item.sliding || item.ttl.is_none());
+    let (positions, items): (Vec<usize>, Vec<_>) = batched
+        .into_iter()
+        .filter_map(|(position, item)| {
+            Some((
+                position,
+                (item.key, CacheValue::from(item.data).stamped(), item.ttl?),
+            ))
+        })
+        .unzip();
+    let keys: Vec<String> = items.iter().map(|(key, _, _)| key.clone()).collect();
+    let mut results: Vec<_> = positions
+        .into_iter()
+        .zip(keys)
+        .zip(cache.insert_items(items).await)
+        .map(|((position, key), result)| (position, BatchResult::write(key, result)))
+        .collect();
+    for (position, item) in single {
+        let result = cache
+            .write_item(
+                item.key.clone(),
+                CacheValue::from(item.data).stamped(),
+                item.ttl,
+                Precondition::Always,
+                &item.tags,
+                item.sliding,
+            )
+            .await
+            .map(|_| ());
+        results.push((position, BatchResult::write(item.key, result)));
+    }
+    results.sort_by_key(|(position, _)| *position);
+    let results = results.into_iter().map(|(_, result)| result).collect();
+    HttpResponse::Ok().json(BatchResponse { results })
+}
+
+#[utoipa::path(
+    post,
+    path = "/cache/batch/delete",
+    request_body = BatchKeys,
+    responses(
+        (status = 200, description = "Per-key results: 200, 500 or 503", body = BatchResponse),
+        (status = 400, description = "Too many keys in the batch, or a reserved key")
+    )
+)]
+pub async fn remove_items(
+    cache: web::Data<Arc<dyn Cache<CacheValue>>>,
+    batch: web::Json<BatchKeys>,
+) -> impl Responder {
+    REQUEST_COUNTER.inc();
+    WRITE_COUNTER.inc();
+    if let Some(response) = batch_too_large(batch.keys.len()) {
+        return response;
+    }
+    if let Some(response) = batch.keys.iter().find_map(|key| reserved_key(key)) {
+        return response;
+    }
+    let keys = batch.into_inner().keys;
+    let outcomes = cache.remove_items(&keys).await;
+    let results = keys
+        .into_iter()
+        .zip(outcomes)
+        .map(|(key, result)| BatchResult::write(key, result))
+        .collect();
+    HttpResponse::Ok().json(BatchResponse { results })
 }
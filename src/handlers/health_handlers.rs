@@ -0,0 +1,20 @@
+use crate::cache::MaintenanceHealth;
+use actix_web::{web, HttpResponse, Responder};
+use serde_json::json;
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "Service and background sweeper are healthy"),
+        (status = 503, description = "Background sweeper is not running")
+    )
+)]
+pub async fn health(maintenance: web::Data<MaintenanceHealth>) -> impl Responder {
+    let body = json!({ "sweeper": maintenance.state() });
+    if maintenance.is_healthy() {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
@@ -1,2 +1,3 @@
 pub mod cache_handlers;
+pub mod health_handlers;
 pub mod metrics_handlers;
@@ -1,14 +1,10 @@
-mod cache;
-mod handlers;
-mod routes;
-
 use actix_web::{web, App, HttpServer};
+use cache_service::{cache, handlers, routes};
 use dotenv::dotenv;
 use log::error;
 use once_cell::sync::Lazy;
 use prometheus::Registry;
 use std::sync::Arc;
-use std::time::Duration;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
@@ -22,24 +18,23 @@ async fn main() -> std::io::Result<()> {
     Lazy::force(&INIT);
 
     let cache = cache::initialize_cache().await;
-    let cache_clone = Arc::clone(&cache);
-
-    tokio::spawn(async move {
-        loop {
-            cache_clone.invalidate_expired(Duration::from_secs(1)).await;
-            tokio::time::sleep(Duration::from_secs(60)).await;
-        }
-    });
+    let maintenance = cache::start_maintenance(Arc::clone(&cache));
+    let health = maintenance.health();
 
     let registry = Registry::new();
     handlers::cache_handlers::init_metrics(&registry);
+    let ttl_limits = handlers::cache_handlers::TtlLimits::from_env();
+    let origins = web::Data::new(cache::OriginLoader::from_env());
 
     let api_doc = routes::ApiDoc::openapi();
 
-    HttpServer::new(move || {
+    let result = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(Arc::clone(&cache)))
             .app_data(web::Data::new(registry.clone()))
+            .app_data(web::Data::new(health.clone()))
+            .app_data(web::Data::new(ttl_limits))
+            .app_data(origins.clone())
             .configure(routes::init)
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-doc/openapi.json", api_doc.clone()),
@@ -48,8 +43,11 @@ async fn main() -> std::io::Result<()> {
     .workers(num_cpus::get() * 2)
     .bind("0.0.0.0:8080")?
     .run()
-    .await
-    .map_err(|e| {
+    .await;
+
+    maintenance.shutdown().await;
+
+    result.map_err(|e| {
         error!("Failed to start server: {}", e);
         e
     })
@@ -58,7 +56,6 @@ async fn main() -> std::io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cache;
     use actix_web::test;
     use prometheus::Registry;
     use std::sync::Arc;
@@ -70,7 +67,7 @@ mod tests {
         let cache = cache::initialize_cache().await;
         let registry = Registry::new();
 
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(web::Data::new(Arc::clone(&cache)))
                 .app_data(web::Data::new(registry.clone()))
@@ -79,7 +76,7 @@ mod tests {
         .await;
 
         let req = test::TestRequest::with_uri("/").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
 
@@ -91,7 +88,7 @@ mod tests {
         let registry = Registry::new();
         let api_doc = routes::ApiDoc::openapi();
 
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(web::Data::new(Arc::clone(&cache)))
                 .app_data(web::Data::new(registry.clone()))
@@ -104,7 +101,7 @@ mod tests {
         .await;
 
         let req = test::TestRequest::with_uri("/swagger-ui/index.html").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
 }
@@ -1,4 +1,4 @@
-use crate::handlers::{cache_handlers, metrics_handlers};
+use crate::handlers::{cache_handlers, health_handlers, metrics_handlers};
 use actix_web::{web, HttpResponse};
 use utoipa::OpenApi;
 
@@ -7,23 +7,84 @@ pub fn init(cfg: &mut web::ServiceConfig) {
         web::resource("/")
             .route(web::get().to(|| async { HttpResponse::Ok().body("..: Cache Service") })),
     )
-    .service(web::resource("/cache").route(web::post().to(cache_handlers::create_item)))
+    .service(
+        web::resource("/cache")
+            .route(web::get().to(cache_handlers::list_keys))
+            .route(web::post().to(cache_handlers::create_item))
+            .route(web::delete().to(cache_handlers::remove_matching)),
+    )
+    .service(
+        web::resource("/cache/batch/get").route(web::post().to(cache_handlers::retrieve_items)),
+    )
+    .service(web::resource("/cache/batch/set").route(web::post().to(cache_handlers::create_items)))
+    .service(
+        web::resource("/cache/batch/delete").route(web::post().to(cache_handlers::remove_items)),
+    )
+    .service(
+        web::resource("/cache/tags/{tag}").route(web::delete().to(cache_handlers::invalidate_tag)),
+    )
+    .service(
+        web::resource("/cache/{key}/incr").route(web::post().to(cache_handlers::increment_item)),
+    )
+    .service(
+        web::resource("/cache/{key}/decr").route(web::post().to(cache_handlers::decrement_item)),
+    )
+    .service(web::resource("/cache/{key}/ttl").route(web::get().to(cache_handlers::retrieve_ttl)))
+    .service(web::resource("/cache/{key}/touch").route(web::post().to(cache_handlers::touch_item)))
+    .service(
+        web::resource("/cache/{key}/persist").route(web::post().to(cache_handlers::persist_item)),
+    )
+    .service(
+        web::resource("/cache/{key}/expire-at")
+            .route(web::post().to(cache_handlers::expire_item_at)),
+    )
     .service(
         web::resource("/cache/{key}")
             .route(web::get().to(cache_handlers::retrieve_item))
+            .route(web::put().to(cache_handlers::put_item))
             .route(web::delete().to(cache_handlers::remove_item)),
     )
-    .service(web::resource("/metrics").route(web::get().to(metrics_handlers::metrics)));
+    .service(web::resource("/metrics").route(web::get().to(metrics_handlers::metrics)))
+    .service(web::resource("/health").route(web::get().to(health_handlers::health)));
 }
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         cache_handlers::create_item,
+        cache_handlers::list_keys,
+        cache_handlers::remove_matching,
+        cache_handlers::invalidate_tag,
         cache_handlers::retrieve_item,
+        cache_handlers::put_item,
         cache_handlers::remove_item,
-        metrics_handlers::metrics
+        cache_handlers::increment_item,
+        cache_handlers::decrement_item,
+        cache_handlers::retrieve_ttl,
+        cache_handlers::touch_item,
+        cache_handlers::persist_item,
+        cache_handlers::expire_item_at,
+        cache_handlers::retrieve_items,
+        cache_handlers::create_items,
+        cache_handlers::remove_items,
+        metrics_handlers::metrics,
+        health_handlers::health
     ),
-    components(schemas(cache_handlers::CacheItem))
+    components(schemas(
+        cache_handlers::CacheItem,
+        cache_handlers::CounterDelta,
+        cache_handlers::CounterValue,
+        cache_handlers::TtlValue,
+        cache_handlers::ErrorBody,
+        cache_handlers::TouchRequest,
+        cache_handlers::ExpireAtRequest,
+        cache_handlers::KeyEntry,
+        cache_handlers::KeyList,
+        cache_handlers::RemovedCount,
+        cache_handlers::BatchKeys,
+        cache_handlers::BatchItems,
+        cache_handlers::BatchResult,
+        cache_handlers::BatchResponse
+    ))
 )]
 pub struct ApiDoc;
@@ -0,0 +1,85 @@
+//! Kills a child process while it appends to a disk cache log, then checks
+//! that reopening the log keeps every complete record. The child is this test
+//! binary itself, running `crash_writer`.
+
+use cache_service::cache::{Cache, DiskCache};
+use std::env;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Log that `crash_writer` appends to; it does nothing if this is unset.
+const LOG_VAR: &str = "DISK_CACHE_CRASH_LOG";
+
+/// Appends to the log named by `LOG_VAR` until it is killed.
+#[tokio::test]
+#[ignore = "run by test_recovers_after_kill_mid_write in a child process"]
+async fn crash_writer() {
+    let Ok(path) = env::var(LOG_VAR) else {
+        return;
+    };
+    let cache = DiskCache::<String>::open(&path, false).unwrap();
+    let value = "x".repeat(4096);
+    for i in 0.. {
+        cache
+            .insert_item(format!("key{}", i), format!("{}:{}", i, value), 600)
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_recovers_after_kill_mid_write() {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = env::temp_dir().join(format!(
+        "cache_service_crash_{}_{}.log",
+        std::process::id(),
+        nanos
+    ));
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["crash_writer", "--exact", "--ignored", "--nocapture"])
+        .env(LOG_VAR, &path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    while fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0) < 1 << 20 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let cache = DiskCache::<String>::open(&path, false).unwrap();
+    let mut recovered = 0;
+    while let Some(value) = cache
+        .retrieve_item(&format!("key{}", recovered))
+        .await
+        .unwrap()
+    {
+        assert!(value.starts_with(&format!("{}:", recovered)));
+        recovered += 1;
+    }
+    assert!(recovered > 0);
+    assert_eq!(
+        cache
+            .retrieve_item(&format!("key{}", recovered + 1))
+            .await
+            .unwrap(),
+        None
+    );
+
+    cache
+        .insert_item("after".into(), "ok".into(), 60)
+        .await
+        .unwrap();
+    drop(cache);
+    let cache = DiskCache::<String>::open(&path, false).unwrap();
+    assert_eq!(
+        cache.retrieve_item("after").await.unwrap(),
+        Some("ok".to_string())
+    );
+    fs::remove_file(&path).unwrap();
+}
@@ -4,10 +4,9 @@ use dotenv::dotenv;
 use log::info;
 use once_cell::sync::Lazy;
 use prometheus::Registry;
-use reqwest;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::spawn;
 use utoipa::OpenApi;
 
 static INIT: Lazy<()> = Lazy::new(|| {
@@ -19,14 +18,23 @@ async fn start_test_server() -> std::io::Result<()> {
     Lazy::force(&INIT);
 
     let cache = cache::initialize_cache().await;
-    let cache_clone = Arc::clone(&cache);
-
-    spawn(async move {
-        cache_clone.invalidate_expired(Duration::from_secs(1)).await;
-    });
+    let maintenance = cache::start_maintenance(Arc::clone(&cache));
+    let health = maintenance.health();
 
     let registry = Registry::new();
     handlers::cache_handlers::init_metrics(&registry);
+    let ttl_limits = handlers::cache_handlers::TtlLimits::from_env();
+    // Keys in the `origin` namespace are loaded from the stub that
+    // `test_read_through` serves on port 8081.
+    let origins = web::Data::new(cache::OriginLoader::new(HashMap::from([(
+        "origin".to_string(),
+        cache::Origin {
+            url: "http://127.0.0.1:8081/items/{id}".to_string(),
+            ttl: 30,
+            timeout_ms: 500,
+            max_wait_ms: None,
+        },
+    )])));
 
     info!("Starting HTTP server");
 
@@ -36,6 +44,9 @@ async fn start_test_server() -> std::io::Result<()> {
         App::new()
             .app_data(web::Data::new(Arc::clone(&cache)))
             .app_data(web::Data::new(registry.clone()))
+            .app_data(web::Data::new(health.clone()))
+            .app_data(web::Data::new(ttl_limits))
+            .app_data(origins.clone())
             .configure(routes::init)
             .service(
                 utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}")
@@ -45,7 +56,10 @@ async fn start_test_server() -> std::io::Result<()> {
     .workers(num_cpus::get() * 2)
     .bind("127.0.0.1:8080")?
     .run()
-    .await
+    .await?;
+
+    maintenance.shutdown().await;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -198,6 +212,716 @@ mod integration_tests {
         server.abort();
     }
 
+    #[actix_rt::test]
+    async fn test_batch_endpoints() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+
+        let res = client
+            .post("http://127.0.0.1:8080/cache/batch/set")
+            .header("Content-Type", "application/json")
+            .body(
+                json!({
+                    "items": [
+                        {"key": "batch_a", "data": "1", "ttl": 10},
+                        {"key": "batch_b", "data": "2", "ttl": 10}
+                    ]
+                })
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["results"][0]["status"], 200);
+        assert_eq!(body["results"][1]["status"], 200);
+
+        let res = client
+            .post("http://127.0.0.1:8080/cache/batch/delete")
+            .header("Content-Type", "application/json")
+            .body(json!({"keys": ["batch_b"]}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+
+        let res = client
+            .post("http://127.0.0.1:8080/cache/batch/get")
+            .header("Content-Type", "application/json")
+            .body(json!({"keys": ["batch_a", "batch_b"]}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["results"][0]["key"], "batch_a");
+        assert_eq!(body["results"][0]["status"], 200);
+        assert_eq!(body["results"][0]["data"], "1");
+        assert_eq!(body["results"][1]["key"], "batch_b");
+        assert_eq!(body["results"][1]["status"], 404);
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_conditional_set() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let set = |value: &str| {
+            client
+                .post("http://127.0.0.1:8080/cache")
+                .header("Content-Type", "application/json")
+                .body(json!({"key": "conditional", "data": value, "ttl": 10}).to_string())
+        };
+
+        let res = set("1").header("If-None-Match", "*").send().await.unwrap();
+        assert!(res.status().is_success());
+        let etag = res.headers()["ETag"].to_str().unwrap().to_string();
+
+        let res = set("2").header("If-None-Match", "*").send().await.unwrap();
+        assert_eq!(res.status(), 412);
+
+        let res = set("2")
+            .header("If-Match", etag.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let new_etag = res.headers()["ETag"].to_str().unwrap().to_string();
+        assert_ne!(new_etag, etag);
+
+        let res = set("3")
+            .header("If-Match", etag.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 412);
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache/conditional")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["ETag"].to_str().unwrap(), new_etag);
+        assert_eq!(res.text().await.unwrap(), "2");
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_counters() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let update = |path: &str, body: serde_json::Value| {
+            client
+                .post(format!("http://127.0.0.1:8080/cache/{}", path))
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+        };
+
+        let res = update("views/incr", json!({"delta": 5, "ttl": 10}))
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["value"], 5);
+
+        let res = update("views/decr", json!({})).await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["value"], 4);
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache/views")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "4");
+
+        let res = client
+            .post("http://127.0.0.1:8080/cache")
+            .header("Content-Type", "application/json")
+            .body(json!({"key": "label", "data": "abc", "ttl": 10}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let res = update("label/incr", json!({})).await.unwrap();
+        assert_eq!(res.status(), 409);
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_list_keys() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        for i in 0..3 {
+            let res = client
+                .post("http://127.0.0.1:8080/cache")
+                .header("Content-Type", "application/json")
+                .body(json!({"key": format!("listed:{}", i), "data": "v", "ttl": 30}).to_string())
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success());
+        }
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache?prefix=listed:&limit=2")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["keys"][0]["key"], "listed:0");
+        assert_eq!(body["keys"][0]["ttl"], 30);
+        assert_eq!(body["keys"][1]["key"], "listed:1");
+        let cursor = body["cursor"].as_str().unwrap().to_string();
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache")
+            .query(&[("prefix", "listed:"), ("limit", "2"), ("cursor", &cursor)])
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["keys"].as_array().unwrap().len(), 1);
+        assert_eq!(body["keys"][0]["key"], "listed:2");
+        assert!(body.get("cursor").is_none());
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache?limit=0")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_bulk_remove() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        for key in ["bulk:1:a", "bulk:1:b", "bulk:2:a", "bulkier"] {
+            let res = client
+                .post("http://127.0.0.1:8080/cache")
+                .header("Content-Type", "application/json")
+                .body(json!({"key": key, "data": "v", "ttl": 30}).to_string())
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success());
+        }
+        let remove = |query: &'static [(&'static str, &'static str)]| {
+            client
+                .delete("http://127.0.0.1:8080/cache")
+                .query(query)
+                .send()
+        };
+
+        let res = remove(&[("pattern", "bulk:*:a")]).await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["removed"], 2);
+
+        let res = remove(&[("prefix", "bulk:")]).await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["removed"], 1);
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache/bulkier")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+
+        let res = remove(&[]).await.unwrap();
+        assert_eq!(res.status(), 400);
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_tag_invalidation() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        for (key, tags) in [
+            ("tagged:a", json!(["product:7", "home"])),
+            ("tagged:b", json!(["product:7"])),
+            ("tagged:c", json!(["home"])),
+        ] {
+            let res = client
+                .post("http://127.0.0.1:8080/cache")
+                .header("Content-Type", "application/json")
+                .body(json!({"key": key, "data": "v", "ttl": 30, "tags": tags}).to_string())
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success());
+        }
+
+        let res = client
+            .delete("http://127.0.0.1:8080/cache/tags/product:7")
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["removed"], 2);
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache/tagged:a")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+        let res = client
+            .get("http://127.0.0.1:8080/cache/tagged:c")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+
+        let res = client
+            .post("http://127.0.0.1:8080/cache")
+            .header("Content-Type", "application/json")
+            .body(json!({"key": "tagged:d", "data": "v", "ttl": 30, "tags": ["a,b"]}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_reserved_keys() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let key_url = "http://127.0.0.1:8080/cache/__tag:home";
+        let requests = [
+            client
+                .post("http://127.0.0.1:8080/cache")
+                .header("Content-Type", "application/json")
+                .body(json!({"key": "__tag:home", "data": "v", "ttl": 30}).to_string()),
+            client.put(key_url).body("v"),
+            client.get(key_url),
+            client.delete(key_url),
+            client.get(format!("{}/ttl", key_url)),
+            client
+                .post(format!("{}/incr", key_url))
+                .header("Content-Type", "application/json")
+                .body(json!({"delta": 1}).to_string()),
+            client
+                .post(format!("{}/touch", key_url))
+                .header("Content-Type", "application/json")
+                .body(json!({"ttl": 30}).to_string()),
+            client
+                .post("http://127.0.0.1:8080/cache/batch/get")
+                .header("Content-Type", "application/json")
+                .body(json!({"keys": ["reserved:ok", "__tag:home"]}).to_string()),
+            client
+                .post("http://127.0.0.1:8080/cache/batch/delete")
+                .header("Content-Type", "application/json")
+                .body(json!({"keys": ["__tag:home"]}).to_string()),
+        ];
+        for request in requests {
+            let res = request.send().await.unwrap();
+            assert_eq!(res.status(), 400);
+            assert!(res.text().await.unwrap().contains("reserved"));
+        }
+
+        // Only the prefix is reserved.
+        let res = client
+            .put("http://127.0.0.1:8080/cache/tag:home?ttl=30")
+            .body("v")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_ttl_management() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let res = client
+            .post("http://127.0.0.1:8080/cache")
+            .header("Content-Type", "application/json")
+            .body(json!({"key": "ttl_key", "data": "v", "ttl": 30}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let ttl = || async {
+            let res = client
+                .get("http://127.0.0.1:8080/cache/ttl_key/ttl")
+                .send()
+                .await
+                .unwrap();
+            let status = res.status();
+            let body = res.text().await.unwrap();
+            (
+                status,
+                serde_json::from_str::<serde_json::Value>(&body).ok(),
+            )
+        };
+        let post = |path: &'static str, body: serde_json::Value| {
+            client
+                .post(format!("http://127.0.0.1:8080/cache/ttl_key/{}", path))
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+        };
+
+        let (_, body) = ttl().await;
+        assert!(body.unwrap()["ttl"].as_u64().unwrap() <= 30);
+
+        let res = post("touch", json!({"ttl": 300})).await.unwrap();
+        assert!(res.status().is_success());
+        let (_, body) = ttl().await;
+        assert!(body.unwrap()["ttl"].as_u64().unwrap() > 30);
+
+        let res = post("persist", json!({})).await.unwrap();
+        assert!(res.status().is_success());
+        let (_, body) = ttl().await;
+        assert!(body.unwrap()["ttl"].is_null());
+
+        let res = post("expire-at", json!({"at": 1})).await.unwrap();
+        assert!(res.status().is_success());
+        let (status, _) = ttl().await;
+        assert_eq!(status, 404);
+        let res = post("touch", json!({"ttl": 300})).await.unwrap();
+        assert_eq!(res.status(), 404);
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_ttl_validation() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let create = |body: serde_json::Value| {
+            client
+                .post("http://127.0.0.1:8080/cache")
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+        };
+
+        let res = create(json!({"key": "zero_ttl", "data": "v", "ttl": 0}))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["error"], "invalid_ttl");
+
+        let res = create(json!({"key": "zero_ttl", "data": "v", "sliding": true}))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = create(json!({"key": "huge_ttl", "data": "v", "ttl": u64::MAX}))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["error"], "ttl_out_of_range");
+        let res = client
+            .post("http://127.0.0.1:8080/cache/huge_ttl/incr")
+            .header("Content-Type", "application/json")
+            .body(json!({"ttl": u64::MAX}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = create(json!({"key": "no_ttl", "data": "v"})).await.unwrap();
+        assert!(res.status().is_success());
+        let res = client
+            .get("http://127.0.0.1:8080/cache/no_ttl/ttl")
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert!(body["ttl"].is_null());
+
+        let res = client
+            .post("http://127.0.0.1:8080/cache/batch/set")
+            .header("Content-Type", "application/json")
+            .body(
+                json!({"items": [
+                    {"key": "batch_ok", "data": "v", "ttl": 60},
+                    {"key": "batch_zero", "data": "v", "ttl": 0}
+                ]})
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["error"], "invalid_ttl");
+        assert!(body["message"].as_str().unwrap().contains("batch_zero"));
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_sliding_expiration() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let res = client
+            .post("http://127.0.0.1:8080/cache")
+            .header("Content-Type", "application/json")
+            .body(json!({"key": "sliding_key", "data": "v", "ttl": 2, "sliding": true}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+
+        for _ in 0..2 {
+            actix_rt::time::sleep(Duration::from_millis(1200)).await;
+            let res = client
+                .get("http://127.0.0.1:8080/cache/sliding_key")
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success());
+        }
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_binary_values() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let blob: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0, 0xff, b'\r', b'\n'];
+        let res = client
+            .put("http://127.0.0.1:8080/cache/binary_key?ttl=10")
+            .header("Content-Type", "image/png")
+            .body(blob.clone())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert!(res.headers().contains_key("etag"));
+
+        let res = client
+            .get("http://127.0.0.1:8080/cache/binary_key")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(res.headers()["content-type"], "image/png");
+        assert_eq!(res.bytes().await.unwrap().to_vec(), blob);
+
+        let res = client
+            .post("http://127.0.0.1:8080/cache/batch/get")
+            .header("Content-Type", "application/json")
+            .body(json!({"keys": ["binary_key"]}).to_string())
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["results"][0]["data_base64"], "iVBORwD/DQo=");
+        assert_eq!(body["results"][0]["content_type"], "image/png");
+
+        // Empty tags, as a bare `tags=` or stray commas leave, are dropped.
+        for query in ["tags=", "tags=binary_tag,", "tags=,binary_tag,,other"] {
+            let res = client
+                .put(format!(
+                    "http://127.0.0.1:8080/cache/binary_key?ttl=10&{}",
+                    query
+                ))
+                .body("x")
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success(), "{}", query);
+        }
+        let res = client
+            .delete("http://127.0.0.1:8080/cache/tags/binary_tag")
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["removed"], 1);
+
+        let res = client
+            .put("http://127.0.0.1:8080/cache/binary_key?ttl=10&tags=a,%01")
+            .body("x")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_conditional_get() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let res = client
+            .post("http://127.0.0.1:8080/cache")
+            .header("Content-Type", "application/json")
+            .body(json!({"key": "polled", "data": "v1", "ttl": 60}).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+
+        let url = "http://127.0.0.1:8080/cache/polled";
+        let res = client.get(url).send().await.unwrap();
+        assert!(res.status().is_success());
+        let etag = res.headers()["etag"].to_str().unwrap().to_string();
+        let last_modified = res.headers()["last-modified"].to_str().unwrap().to_string();
+        assert!(res.headers().contains_key("age"));
+        let cache_control = res.headers()["cache-control"].to_str().unwrap();
+        let max_age: u64 = cache_control
+            .strip_prefix("max-age=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&max_age));
+
+        let res = client
+            .get(url)
+            .header("If-None-Match", &etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()["etag"], etag.as_str());
+        assert_eq!(res.text().await.unwrap(), "");
+
+        let res = client
+            .get(url)
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+        let res = client
+            .get(url)
+            .header("If-None-Match", "\"0\"")
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(res.text().await.unwrap(), "v1");
+
+        server.abort();
+    }
+
+    #[actix_rt::test]
+    async fn test_read_through() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = actix_rt::spawn(start_test_server());
+        let fetches = web::Data::new(AtomicUsize::new(0));
+        let counted = fetches.clone();
+        let origin = HttpServer::new(move || {
+            App::new().app_data(counted.clone()).route(
+                "/items/{id}",
+                web::get().to(
+                    |id: web::Path<String>, fetches: web::Data<AtomicUsize>| async move {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        match id.as_str() {
+                            "missing" => actix_web::HttpResponse::NotFound().finish(),
+                            "broken" => actix_web::HttpResponse::InternalServerError().finish(),
+                            "slow" => {
+                                actix_rt::time::sleep(Duration::from_secs(2)).await;
+                                actix_web::HttpResponse::Ok().finish()
+                            }
+                            id => actix_web::HttpResponse::Ok()
+                                .content_type("text/plain")
+                                .body(format!("fetched {}", id)),
+                        }
+                    },
+                ),
+            )
+        })
+        .workers(1)
+        .bind("127.0.0.1:8081")
+        .unwrap()
+        .run();
+        let origin = actix_rt::spawn(origin);
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        for _ in 0..2 {
+            let res = client
+                .get("http://127.0.0.1:8080/cache/origin:a")
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success());
+            assert_eq!(res.headers()["content-type"], "text/plain");
+            assert_eq!(res.headers()["cache-control"], "max-age=30");
+            assert_eq!(res.text().await.unwrap(), "fetched a");
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        let status = |key: &'static str| {
+            let request = client
+                .get(format!("http://127.0.0.1:8080/cache/{}", key))
+                .send();
+            async move { request.await.unwrap().status().as_u16() }
+        };
+        assert_eq!(status("origin:missing").await, 404);
+        assert_eq!(status("origin:broken").await, 502);
+        assert_eq!(status("origin:slow").await, 504);
+        assert_eq!(status("elsewhere:a").await, 404);
+
+        origin.abort();
+        server.abort();
+    }
+
     #[actix_rt::test]
     async fn test_metrics() {
         let server = actix_rt::spawn(start_test_server());
@@ -221,4 +945,23 @@ mod integration_tests {
 
         server.abort();
     }
+
+    #[actix_rt::test]
+    async fn test_health() {
+        let server = actix_rt::spawn(start_test_server());
+
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+        let client = reqwest::Client::new();
+        let res = client
+            .get("http://127.0.0.1:8080/health")
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body["sweeper"], "running");
+
+        server.abort();
+    }
 }
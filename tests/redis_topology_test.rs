@@ -0,0 +1,331 @@
+//! Runs `RedisCache` against Redis Cluster and Sentinel deployments made of
+//! locally spawned `redis-server` processes. Needs `redis-server` and
+//! `redis-cli` on the `PATH`:
+//!
+//!     cargo test --test redis_topology_test -- --ignored
+
+use cache_service::cache::{Cache, RedisCache, RedisTopology};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A `redis-server` that is killed when dropped.
+struct RedisProcess {
+    port: u16,
+    child: Child,
+}
+
+impl RedisProcess {
+    fn spawn(port: u16, args: &[&str]) -> Self {
+        let child = Command::new("redis-server")
+            .args(args)
+            .args([
+                "--port",
+                &port.to_string(),
+                "--save",
+                "",
+                "--appendonly",
+                "no",
+            ])
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("redis-server must be on the PATH");
+        let process = Self { port, child };
+        wait_until(|| process.connection().is_ok());
+        process
+    }
+
+    fn url(&self) -> String {
+        format!("redis://127.0.0.1:{}", self.port)
+    }
+
+    fn connection(&self) -> redis::RedisResult<redis::Connection> {
+        redis::Client::open(self.url())?.get_connection()
+    }
+
+    fn query<T: redis::FromRedisValue>(&self, cmd: &mut redis::Cmd) -> redis::RedisResult<T> {
+        cmd.query(&mut self.connection()?)
+    }
+}
+
+impl Drop for RedisProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn wait_until(mut ready: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !ready() {
+        assert!(Instant::now() < deadline, "timed out waiting for redis");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cache_service_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Three primaries sharing the 16384 slots, without replicas.
+fn spawn_cluster(name: &str, base_port: u16) -> Vec<RedisProcess> {
+    let dir = scratch_dir(name);
+    let nodes: Vec<_> = (base_port..base_port + 3)
+        .map(|port| {
+            let config = dir.join(format!("nodes-{}.conf", port));
+            RedisProcess::spawn(
+                port,
+                &[
+                    "--cluster-enabled",
+                    "yes",
+                    "--cluster-config-file",
+                    config.to_str().unwrap(),
+                ],
+            )
+        })
+        .collect();
+    let status = Command::new("redis-cli")
+        .arg("--cluster")
+        .arg("create")
+        .args(nodes.iter().map(|node| format!("127.0.0.1:{}", node.port)))
+        .args(["--cluster-replicas", "0", "--cluster-yes"])
+        .stdout(Stdio::null())
+        .status()
+        .expect("redis-cli must be on the PATH");
+    assert!(status.success());
+    wait_until(|| {
+        nodes.iter().all(|node| {
+            node.query::<String>(redis::cmd("CLUSTER").arg("INFO"))
+                .is_ok_and(|info| info.contains("cluster_state:ok"))
+        })
+    });
+    nodes
+}
+
+async fn connect_cluster(nodes: &[RedisProcess]) -> RedisCache {
+    let topology = RedisTopology::Cluster(nodes.iter().map(RedisProcess::url).collect());
+    RedisCache::connect(&topology).await.unwrap()
+}
+
+/// Returns the node that serves `key` and one that does not.
+fn owner_and_other<'a>(
+    nodes: &'a [RedisProcess],
+    key: &str,
+) -> (&'a RedisProcess, &'a RedisProcess) {
+    let owner = nodes
+        .iter()
+        .find(|node| {
+            node.query::<Option<String>>(redis::cmd("GET").arg(key))
+                .is_ok()
+        })
+        .unwrap();
+    let other = nodes.iter().find(|node| node.port != owner.port).unwrap();
+    (owner, other)
+}
+
+/// Starts moving `key`'s slot from `source` to `target` and migrates the key.
+/// Until the move is finalized, `source` answers requests for it with `ASK`.
+fn migrate_key(source: &RedisProcess, target: &RedisProcess, key: &str) -> u16 {
+    let slot: u16 = source
+        .query(redis::cmd("CLUSTER").arg("KEYSLOT").arg(key))
+        .unwrap();
+    let source_id: String = source.query(redis::cmd("CLUSTER").arg("MYID")).unwrap();
+    let target_id: String = target.query(redis::cmd("CLUSTER").arg("MYID")).unwrap();
+    let _: () = target
+        .query(
+            redis::cmd("CLUSTER")
+                .arg("SETSLOT")
+                .arg(slot)
+                .arg("IMPORTING")
+                .arg(&source_id),
+        )
+        .unwrap();
+    let _: () = source
+        .query(
+            redis::cmd("CLUSTER")
+                .arg("SETSLOT")
+                .arg(slot)
+                .arg("MIGRATING")
+                .arg(&target_id),
+        )
+        .unwrap();
+    let _: () = source
+        .query(
+            redis::cmd("MIGRATE")
+                .arg("127.0.0.1")
+                .arg(target.port)
+                .arg(key)
+                .arg(0)
+                .arg(5000),
+        )
+        .unwrap();
+    slot
+}
+
+#[tokio::test]
+#[ignore = "spawns redis-server processes"]
+async fn test_cluster_routes_keys_by_slot() {
+    let nodes = spawn_cluster("cluster_slots", 17000);
+    let cache = connect_cluster(&nodes).await;
+
+    for i in 0..100 {
+        cache
+            .insert_item(format!("key{}", i), i.to_string(), 60)
+            .await
+            .unwrap();
+    }
+    for i in 0..100 {
+        let value: Option<String> = cache.retrieve_item(&format!("key{}", i)).await.unwrap();
+        assert_eq!(value, Some(i.to_string()));
+    }
+    for node in &nodes {
+        let keys: usize = node.query(&mut redis::cmd("DBSIZE")).unwrap();
+        assert!(keys > 0, "node {} holds no keys", node.port);
+    }
+}
+
+#[tokio::test]
+#[ignore = "spawns redis-server processes"]
+async fn test_cluster_scan_visits_every_primary() {
+    let nodes = spawn_cluster("cluster_scan", 17400);
+    let cache = connect_cluster(&nodes).await;
+    for i in 0..100 {
+        cache
+            .insert_item(format!("scan:{}", i), i.to_string(), 60)
+            .await
+            .unwrap();
+    }
+
+    let mut listed = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = Cache::<String>::scan_keys(&cache, "scan:", cursor.as_deref(), 10)
+            .await
+            .unwrap();
+        listed.extend(page.keys.into_iter().map(|info| info.key));
+        match page.cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    listed.sort();
+    listed.dedup();
+    assert_eq!(listed.len(), 100);
+}
+
+#[tokio::test]
+#[ignore = "spawns redis-server processes"]
+async fn test_cluster_follows_moved() {
+    let nodes = spawn_cluster("cluster_moved", 17100);
+    let cache = connect_cluster(&nodes).await;
+    cache
+        .insert_item("moved".to_string(), "1".to_string(), 60)
+        .await
+        .unwrap();
+
+    let (source, target) = owner_and_other(&nodes, "moved");
+    let slot = migrate_key(source, target, "moved");
+    let target_id: String = target.query(redis::cmd("CLUSTER").arg("MYID")).unwrap();
+    for node in &nodes {
+        let _: () = node
+            .query(
+                redis::cmd("CLUSTER")
+                    .arg("SETSLOT")
+                    .arg(slot)
+                    .arg("NODE")
+                    .arg(&target_id),
+            )
+            .unwrap();
+    }
+
+    let value: Option<String> = cache.retrieve_item("moved").await.unwrap();
+    assert_eq!(value, Some("1".to_string()));
+    cache
+        .insert_item("moved".to_string(), "2".to_string(), 60)
+        .await
+        .unwrap();
+    let stored: Option<String> = target.query(redis::cmd("GET").arg("moved")).unwrap();
+    assert!(stored.is_some_and(|stored| stored.ends_with(":\"2\"")));
+}
+
+#[tokio::test]
+#[ignore = "spawns redis-server processes"]
+async fn test_cluster_follows_ask() {
+    let nodes = spawn_cluster("cluster_ask", 17200);
+    let cache = connect_cluster(&nodes).await;
+    cache
+        .insert_item("asked".to_string(), "1".to_string(), 60)
+        .await
+        .unwrap();
+
+    let (source, target) = owner_and_other(&nodes, "asked");
+    migrate_key(source, target, "asked");
+
+    let value: Option<String> = cache.retrieve_item("asked").await.unwrap();
+    assert_eq!(value, Some("1".to_string()));
+}
+
+#[tokio::test]
+#[ignore = "spawns redis-server processes"]
+async fn test_sentinel_follows_failover() {
+    let dir = scratch_dir("sentinel");
+    let primary = RedisProcess::spawn(17300, &[]);
+    let replica = RedisProcess::spawn(17301, &["--replicaof", "127.0.0.1", "17300"]);
+    let config = dir.join("sentinel.conf");
+    fs::write(
+        &config,
+        "sentinel monitor mymaster 127.0.0.1 17300 1\n\
+         sentinel down-after-milliseconds mymaster 1000\n\
+         sentinel failover-timeout mymaster 3000\n",
+    )
+    .unwrap();
+    let sentinel = RedisProcess::spawn(17302, &[config.to_str().unwrap(), "--sentinel"]);
+    wait_until(|| {
+        sentinel
+            .query::<Vec<redis::Value>>(redis::cmd("SENTINEL").arg("REPLICAS").arg("mymaster"))
+            .is_ok_and(|replicas| !replicas.is_empty())
+    });
+
+    let topology = RedisTopology::Sentinel {
+        sentinels: vec![sentinel.url()],
+        service: "mymaster".to_string(),
+    };
+    let cache = RedisCache::connect(&topology).await.unwrap();
+    cache
+        .insert_item("before".to_string(), "1".to_string(), 60)
+        .await
+        .unwrap();
+    wait_until(|| {
+        replica
+            .query::<Option<String>>(redis::cmd("GET").arg("before"))
+            .is_ok_and(|value| value.is_some())
+    });
+
+    let _: () = sentinel
+        .query(redis::cmd("SENTINEL").arg("FAILOVER").arg("mymaster"))
+        .unwrap();
+    wait_until(|| {
+        replica
+            .query::<Vec<redis::Value>>(&mut redis::cmd("ROLE"))
+            .is_ok_and(|role| role.first() == Some(&redis::Value::BulkString(b"master".to_vec())))
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while let Err(e) = cache
+        .insert_item("after".to_string(), "2".to_string(), 60)
+        .await
+    {
+        assert!(Instant::now() < deadline, "writes never recovered: {}", e);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let stored: Option<String> = replica.query(redis::cmd("GET").arg("after")).unwrap();
+    assert!(stored.is_some_and(|stored| stored.ends_with(":\"2\"")));
+    let value: Option<String> = cache.retrieve_item("before").await.unwrap();
+    assert_eq!(value, Some("1".to_string()));
+    drop(primary);
+}